@@ -1,4 +1,4 @@
-use cli_clipboard::{ClipboardContext, ClipboardProvider};
+use cli_clipboard::{ClipboardContext, ClipboardOps, ClipboardProvider};
 
 fn main() {
     let mut ctx = ClipboardContext::new().unwrap();
@@ -0,0 +1,23 @@
+extern crate cli_clipboard;
+
+#[cfg(target_os = "linux")]
+use cli_clipboard::x11_clipboard::{self, X11ClipboardContext};
+#[cfg(target_os = "linux")]
+use cli_clipboard::{ClipboardOps, ClipboardProvider};
+
+#[cfg(target_os = "linux")]
+fn main() {
+    // Serves the contents when started again by set_persistent
+    x11_clipboard::serve_if_requested();
+
+    let mut ctx: X11ClipboardContext = X11ClipboardContext::new().unwrap();
+    ctx.set_persistent(true);
+
+    ctx.set_contents("Still here after exit".to_owned())
+        .unwrap();
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    println!("Persistent selections are only available under linux!");
+}
@@ -3,7 +3,7 @@ extern crate cli_clipboard;
 #[cfg(target_os = "linux")]
 use cli_clipboard::x11_clipboard::{Primary, X11ClipboardContext};
 #[cfg(target_os = "linux")]
-use cli_clipboard::ClipboardProvider;
+use cli_clipboard::{ClipboardOps, ClipboardProvider};
 
 #[cfg(target_os = "linux")]
 fn main() {
@@ -1,4 +1,4 @@
-use crate::common::ClipboardProvider;
+use crate::common::{ClipboardOps, ClipboardProvider};
 use jni::objects::JString;
 use std::ffi::CStr;
 
@@ -10,7 +10,9 @@ impl ClipboardProvider for AndroidClipboardContext {
     fn new() -> Result<Self> {
         Ok(AndroidClipboardContext)
     }
+}
 
+impl ClipboardOps for AndroidClipboardContext {
     fn get_contents(&mut self) -> Result<String> {
         let ctx = ndk_glue::native_activity();
 
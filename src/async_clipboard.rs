@@ -0,0 +1,233 @@
+//! Async clipboard access for tokio, enabled by the `async` feature.
+//!
+//! The Linux backends block while talking to the display server: X11
+//! waits for the selection owner to answer and Wayland reads a pipe to
+//! completion. [`AsyncClipboardContext`] runs these calls on tokio's
+//! blocking thread pool so that they never stall the runtime's worker
+//! threads.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use cli_clipboard::async_clipboard::{AsyncClipboardContext, AsyncClipboardProvider};
+//! use cli_clipboard::linux_clipboard::LinuxClipboardContext;
+//!
+//! # async fn example() -> cli_clipboard::Result<()> {
+//! let mut ctx = AsyncClipboardContext::<LinuxClipboardContext>::new().await?;
+//! ctx.set_contents("some string".to_owned()).await?;
+//!
+//! let mut changes = ctx.changes().await?;
+//! while let Some(change) = changes.next().await {
+//!     println!("{:?}: {}", change?, ctx.get_contents().await?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::common::ClipboardProvider;
+use crate::linux_clipboard::LinuxClipboardContext;
+use crate::watcher::{ClipboardChange, ClipboardWatcher, StopHandle};
+use crate::wayland_clipboard::WaylandClipboardContext;
+use crate::x11_clipboard::{Selection, X11ClipboardContext};
+use crate::{Error, Result};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task;
+
+/// Number of changes buffered before the watcher waits for the
+/// receiver to catch up
+const CHANGES_BUFFER: usize = 16;
+
+/// The async counterpart of [`ClipboardProvider`].
+pub trait AsyncClipboardProvider: Sized {
+    /// Create a context with which to access the clipboard
+    fn new() -> impl Future<Output = Result<Self>> + Send;
+    /// Method to get the clipboard contents as a String
+    fn get_contents(&mut self) -> impl Future<Output = Result<String>> + Send;
+    /// Method to set the clipboard contents as a String
+    fn set_contents(&mut self, data: String) -> impl Future<Output = Result<()>> + Send;
+    /// Method to clear the clipboard contents
+    fn clear(&mut self) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Runs a blocking clipboard context on tokio's blocking thread pool.
+///
+/// `AsyncClipboardProvider` is implemented for any wrapped
+/// `ClipboardProvider`. [`changes`](Self::changes) is available for
+/// `LinuxClipboardContext`, `X11ClipboardContext` and
+/// `WaylandClipboardContext`.
+pub struct AsyncClipboardContext<C = LinuxClipboardContext> {
+    inner: Arc<Mutex<C>>,
+}
+
+impl<C> AsyncClipboardContext<C>
+where
+    C: ClipboardProvider + Send + 'static,
+{
+    /// Wraps an existing context.
+    pub fn from_context(context: C) -> AsyncClipboardContext<C> {
+        AsyncClipboardContext {
+            inner: Arc::new(Mutex::new(context)),
+        }
+    }
+
+    async fn create() -> Result<AsyncClipboardContext<C>> {
+        let context = task::spawn_blocking(C::new)
+            .await
+            .map_err(|e| Error::Backend(Box::new(e)))??;
+        Ok(AsyncClipboardContext::from_context(context))
+    }
+
+    /// Calls `f` with the context on the blocking thread pool. The call
+    /// runs to completion even if the returned future is dropped.
+    fn run<T, F>(&self, f: F) -> impl Future<Output = Result<T>> + Send
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut C) -> Result<T> + Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        async move {
+            task::spawn_blocking(move || {
+                let mut context = inner
+                    .lock()
+                    .map_err(|_| Error::Backend("clipboard context lock poisoned".into()))?;
+                f(&mut context)
+            })
+            .await
+            .map_err(|e| Error::Backend(Box::new(e)))?
+        }
+    }
+}
+
+impl<C> AsyncClipboardProvider for AsyncClipboardContext<C>
+where
+    C: ClipboardProvider + Send + 'static,
+{
+    fn new() -> impl Future<Output = Result<Self>> + Send {
+        Self::create()
+    }
+
+    fn get_contents(&mut self) -> impl Future<Output = Result<String>> + Send {
+        self.run(|context| context.get_contents())
+    }
+
+    fn set_contents(&mut self, data: String) -> impl Future<Output = Result<()>> + Send {
+        self.run(move |context| context.set_contents(data))
+    }
+
+    fn clear(&mut self) -> impl Future<Output = Result<()>> + Send {
+        self.run(|context| context.clear())
+    }
+}
+
+/// Contexts whose selection can be watched for changes
+pub trait WatchableContext {
+    /// The selection the context accesses
+    type Selection: Selection;
+}
+
+impl<S: Selection> WatchableContext for LinuxClipboardContext<S> {
+    type Selection = S;
+}
+
+impl<S: Selection> WatchableContext for WaylandClipboardContext<S> {
+    type Selection = S;
+}
+
+impl<S: Selection> WatchableContext for X11ClipboardContext<S> {
+    type Selection = S;
+}
+
+impl<C> AsyncClipboardContext<C>
+where
+    C: WatchableContext,
+{
+    /// Starts watching the selection this context accesses.
+    pub async fn changes(&self) -> Result<ClipboardChanges> {
+        ClipboardChanges::watch::<C::Selection>().await
+    }
+}
+
+/// Async stream of selection changes, fed by a [`ClipboardWatcher`]
+/// running on its own thread.
+///
+/// The watcher is stopped when this is dropped.
+pub struct ClipboardChanges {
+    receiver: mpsc::Receiver<Result<ClipboardChange>>,
+    stop: StopHandle,
+}
+
+impl ClipboardChanges {
+    async fn watch<S>() -> Result<ClipboardChanges>
+    where
+        S: Selection + 'static,
+    {
+        let (ready, started) = oneshot::channel();
+        let (sender, receiver) = mpsc::channel(CHANGES_BUFFER);
+
+        // The watcher is not Send, so it is created on its thread.
+        thread::spawn(move || {
+            let watcher = match ClipboardWatcher::<S>::new() {
+                Ok(watcher) => watcher,
+                Err(e) => {
+                    let _ = ready.send(Err(e));
+                    return;
+                }
+            };
+            if ready.send(Ok(watcher.stop_handle())).is_err() {
+                return;
+            }
+
+            for change in watcher {
+                if sender.blocking_send(change).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let stop = started.await.map_err(|e| Error::Backend(Box::new(e)))??;
+        Ok(ClipboardChanges { receiver, stop })
+    }
+
+    /// Waits for the next change. Returns `None` once the watcher has
+    /// ended, which happens after it reports an error.
+    pub async fn next(&mut self) -> Option<Result<ClipboardChange>> {
+        self.receiver.recv().await
+    }
+}
+
+impl Drop for ClipboardChanges {
+    fn drop(&mut self) {
+        self.stop.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::ClipboardOps;
+    use crate::mock_clipboard::MockClipboardContext;
+
+    #[tokio::test]
+    async fn runs_blocking_contexts() {
+        let mock = MockClipboardContext::new().unwrap();
+        let mut ctx = AsyncClipboardContext::from_context(mock.clone());
+
+        ctx.set_contents("async".to_owned()).await.unwrap();
+        assert_eq!(ctx.get_contents().await.unwrap(), "async");
+        assert_eq!(mock.clone().get_contents().unwrap(), "async");
+
+        ctx.clear().await.unwrap();
+        assert_eq!(ctx.get_contents().await.unwrap(), "");
+
+        mock.fail_next(Error::Timeout);
+        assert!(matches!(ctx.get_contents().await, Err(Error::Timeout)));
+
+        let mut created = AsyncClipboardContext::<MockClipboardContext>::new()
+            .await
+            .unwrap();
+        assert_eq!(created.get_contents().await.unwrap(), "");
+    }
+}
@@ -0,0 +1,280 @@
+//! Clipboard accessed through external programs like xclip.
+
+use crate::common::*;
+use crate::deadline::TIMEOUT;
+use crate::x11_clipboard::{Clipboard, Selection};
+use crate::{process, Error, Result};
+use std::env;
+use std::fs;
+use std::marker::PhantomData;
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// A program followed by its arguments
+type CommandLine = &'static [&'static str];
+
+/// Clipboard programs with built-in command lines
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Preset {
+    /// `xclip`
+    Xclip,
+    /// `xsel`
+    Xsel,
+    /// `wl-copy` and `wl-paste` from wl-clipboard
+    WlClipboard,
+}
+
+impl Preset {
+    /// Returns the first preset usable in this session: wl-clipboard if
+    /// `$WAYLAND_DISPLAY` is set, and xclip or xsel if `$DISPLAY` is
+    /// set, provided the programs are installed.
+    pub fn detect() -> Option<Preset> {
+        let wayland = env::var_os("WAYLAND_DISPLAY").is_some();
+        let x11 = env::var_os("DISPLAY").is_some();
+
+        [
+            (Preset::WlClipboard, wayland),
+            (Preset::Xclip, x11),
+            (Preset::Xsel, x11),
+        ]
+        .iter()
+        .find(|(preset, usable)| *usable && installed(preset.program()))
+        .map(|(preset, _)| *preset)
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            Preset::Xclip => "xclip",
+            Preset::Xsel => "xsel",
+            Preset::WlClipboard => "wl-paste",
+        }
+    }
+
+    /// Returns the copy, paste and clear commands for the selection.
+    fn commands(self, primary: bool) -> (CommandLine, CommandLine, Option<CommandLine>) {
+        match (self, primary) {
+            (Preset::Xclip, false) => (
+                &["xclip", "-selection", "clipboard", "-in"],
+                &["xclip", "-selection", "clipboard", "-out"],
+                None,
+            ),
+            (Preset::Xclip, true) => (
+                &["xclip", "-selection", "primary", "-in"],
+                &["xclip", "-selection", "primary", "-out"],
+                None,
+            ),
+            (Preset::Xsel, false) => (
+                &["xsel", "--clipboard", "--input"],
+                &["xsel", "--clipboard", "--output"],
+                Some(&["xsel", "--clipboard", "--clear"]),
+            ),
+            (Preset::Xsel, true) => (
+                &["xsel", "--primary", "--input"],
+                &["xsel", "--primary", "--output"],
+                Some(&["xsel", "--primary", "--clear"]),
+            ),
+            (Preset::WlClipboard, false) => (
+                &["wl-copy"],
+                &["wl-paste", "--no-newline"],
+                Some(&["wl-copy", "--clear"]),
+            ),
+            (Preset::WlClipboard, true) => (
+                &["wl-copy", "--primary"],
+                &["wl-paste", "--primary", "--no-newline"],
+                Some(&["wl-copy", "--primary", "--clear"]),
+            ),
+        }
+    }
+}
+
+/// Clipboard accessed by running external programs.
+///
+/// Contents are piped to the standard input of the copy command and
+/// read from the standard output of the paste command. Commands are
+/// killed if they run longer than the timeout.
+///
+/// Command lines are built in for xclip, xsel and wl-clipboard, see
+/// [`Preset`], and any other programs can be used with
+/// [`with_commands`](Self::with_commands). Presets access the selection
+/// named by the type parameter, while custom commands decide for
+/// themselves.
+///
+/// A failing command returns `Error::Backend` with its error output.
+/// Some programs fail that way when the clipboard is empty.
+///
+/// # Example
+///
+/// ```no_run
+/// use cli_clipboard::command_clipboard::CommandClipboardContext;
+/// use cli_clipboard::{ClipboardOps, ClipboardProvider};
+///
+/// let mut ctx: CommandClipboardContext =
+///     CommandClipboardContext::with_commands(&["pbcopy"], &["pbpaste"]);
+/// ctx.set_contents("copied by pbcopy".to_owned()).unwrap();
+/// ```
+pub struct CommandClipboardContext<S = Clipboard>
+where
+    S: Selection,
+{
+    copy: Vec<String>,
+    paste: Vec<String>,
+    clear: Option<Vec<String>>,
+    timeout: Duration,
+    selection: PhantomData<S>,
+}
+
+impl<S> ClipboardProvider for CommandClipboardContext<S>
+where
+    S: Selection,
+{
+    /// Uses the preset returned by [`Preset::detect`], failing with
+    /// `Error::NoDisplay` if there is none.
+    fn new() -> Result<CommandClipboardContext<S>> {
+        match Preset::detect() {
+            Some(preset) => Ok(CommandClipboardContext::with_preset(preset)),
+            None => Err(Error::NoDisplay("no clipboard program found".into())),
+        }
+    }
+}
+
+impl<S> ClipboardOps for CommandClipboardContext<S>
+where
+    S: Selection,
+{
+    fn get_contents(&mut self) -> Result<String> {
+        self.get_contents_with_deadline(self.deadline())
+    }
+
+    fn set_contents(&mut self, data: String) -> Result<()> {
+        self.set_contents_with_deadline(data, self.deadline())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.clear_with_deadline(self.deadline())
+    }
+
+    fn get_contents_with_deadline(&mut self, deadline: Instant) -> Result<String> {
+        let contents = process::run(&mut command(&self.paste), None, true, deadline)?;
+        Ok(String::from_utf8(contents)?)
+    }
+
+    fn set_contents_with_deadline(&mut self, data: String, deadline: Instant) -> Result<()> {
+        process::run(
+            &mut command(&self.copy),
+            Some(data.into_bytes()),
+            false,
+            deadline,
+        )?;
+        Ok(())
+    }
+
+    /// Runs the clear command, or copies empty contents if there is
+    /// none.
+    fn clear_with_deadline(&mut self, deadline: Instant) -> Result<()> {
+        match &self.clear {
+            Some(clear) => process::run(&mut command(clear), None, false, deadline)?,
+            None => process::run(&mut command(&self.copy), Some(Vec::new()), false, deadline)?,
+        };
+        Ok(())
+    }
+}
+
+impl<S> CommandClipboardContext<S>
+where
+    S: Selection,
+{
+    /// Uses the command lines of `preset` for the selection.
+    pub fn with_preset(preset: Preset) -> CommandClipboardContext<S> {
+        let (copy, paste, clear) = preset.commands(S::is_primary());
+        let mut context = CommandClipboardContext::with_commands(copy, paste);
+        context.set_clear_command(clear);
+        context
+    }
+
+    /// Uses custom commands, given as a program followed by its
+    /// arguments. The copy command reads the contents from its standard
+    /// input, and the paste command writes them to its standard output.
+    pub fn with_commands(copy: &[&str], paste: &[&str]) -> CommandClipboardContext<S> {
+        CommandClipboardContext {
+            copy: to_strings(copy),
+            paste: to_strings(paste),
+            clear: None,
+            timeout: TIMEOUT,
+            selection: PhantomData,
+        }
+    }
+
+    /// Sets the command that clears the clipboard. Without one, empty
+    /// contents are copied instead.
+    pub fn set_clear_command(&mut self, clear: Option<&[&str]>) {
+        self.clear = clear.map(to_strings);
+    }
+
+    /// Sets how long a command may run before it is killed and the
+    /// operation fails with `Error::Timeout`. Defaults to 3 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn deadline(&self) -> Instant {
+        Instant::now() + self.timeout
+    }
+}
+
+fn to_strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|&arg| arg.to_owned()).collect()
+}
+
+/// Builds the command for a program followed by its arguments. An empty
+/// command line names no program, which fails to start.
+fn command(args: &[String]) -> Command {
+    let mut command = Command::new(args.first().map_or("", String::as_str));
+    command.args(args.iter().skip(1));
+    command
+}
+
+/// Checks whether `program` is an executable file in `$PATH`.
+fn installed(program: &str) -> bool {
+    let path = env::var_os("PATH").unwrap_or_default();
+    env::split_paths(&path).any(|dir| match fs::metadata(dir.join(program)) {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::x11_clipboard::Primary;
+
+    #[test]
+    fn custom_commands() {
+        let path = env::temp_dir().join(format!("cli-clipboard-command-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let copy = format!("cat > {}", path);
+        let paste = format!("cat {}", path);
+
+        let mut ctx: CommandClipboardContext =
+            CommandClipboardContext::with_commands(&["sh", "-c", &copy], &["sh", "-c", &paste]);
+        ctx.set_contents("via\ncommands".to_owned()).unwrap();
+        assert_eq!(ctx.get_contents().unwrap(), "via\ncommands");
+        ctx.clear().unwrap();
+        assert_eq!(ctx.get_contents().unwrap(), "");
+        fs::remove_file(path).unwrap();
+
+        ctx.set_timeout(Duration::from_millis(50));
+        ctx.set_clear_command(Some(&["sleep", "5"]));
+        assert!(matches!(ctx.clear(), Err(Error::Timeout)));
+    }
+
+    #[test]
+    fn presets() {
+        let ctx: CommandClipboardContext<Primary> =
+            CommandClipboardContext::with_preset(Preset::Xsel);
+        assert_eq!(ctx.paste, ["xsel", "--primary", "--output"]);
+        assert!(installed("sh"));
+        assert!(!installed("cli-clipboard-missing"));
+    }
+}
@@ -14,18 +14,320 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use crate::Result;
+use crate::{files, html, mime, Error, ImageData, Operation, Result};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-/// Trait for clipboard access
-pub trait ClipboardProvider: Sized {
+/// Trait for creating clipboard contexts
+///
+/// The operations are in the [`ClipboardOps`] supertrait, which unlike this
+/// trait can be used as a trait object, e.g. to pick a provider at
+/// runtime as a `Box<dyn ClipboardOps>`.
+pub trait ClipboardProvider: ClipboardOps + Sized {
     /// Create a context with which to access the clipboard
     fn new() -> Result<Self>;
+}
+
+/// Trait for clipboard access
+///
+/// Implemented for `Box<dyn ClipboardOps>`, `&mut T` and `Arc<Mutex<T>>`
+/// of any clipboard `T`, so these can be passed wherever a clipboard is
+/// expected.
+pub trait ClipboardOps {
     /// Method to get the clipboard contents as a String
     fn get_contents(&mut self) -> Result<String>;
     /// Method to set the clipboard contents as a String
     fn set_contents(&mut self, content: String) -> Result<()>;
-    // TODO: come up with some platform-agnostic API for richer types
-    // than just strings (c.f. issue #31)
     /// Method to clear the clipboard
     fn clear(&mut self) -> Result<()>;
+    /// Method to get the clipboard contents as a String, giving up with
+    /// [`Error::Timeout`] once the deadline passes
+    ///
+    /// Backends whose operations cannot stall ignore the deadline.
+    fn get_contents_with_deadline(&mut self, _deadline: Instant) -> Result<String> {
+        self.get_contents()
+    }
+    /// Method to set the clipboard contents as a String, giving up with
+    /// [`Error::Timeout`] once the deadline passes
+    fn set_contents_with_deadline(&mut self, content: String, _deadline: Instant) -> Result<()> {
+        self.set_contents(content)
+    }
+    /// Method to clear the clipboard, giving up with [`Error::Timeout`]
+    /// once the deadline passes
+    fn clear_with_deadline(&mut self, _deadline: Instant) -> Result<()> {
+        self.clear()
+    }
+    /// Method to get the clipboard contents in the given MIME type as bytes
+    ///
+    /// Backends without support for arbitrary formats only provide plain
+    /// text, and return [`Error::FormatUnavailable`] for anything else.
+    fn get_bytes(&mut self, mime: &str) -> Result<Vec<u8>> {
+        if mime::is_text(mime) {
+            Ok(self.get_contents()?.into_bytes())
+        } else {
+            Err(Error::FormatUnavailable)
+        }
+    }
+    /// Method to set the clipboard contents to bytes in the given MIME type
+    ///
+    /// Backends without support for arbitrary formats only accept plain
+    /// UTF-8 text, and return [`Error::Unsupported`] for anything else.
+    fn set_bytes(&mut self, mime: &str, data: Vec<u8>) -> Result<()> {
+        if mime::is_text(mime) {
+            self.set_contents(String::from_utf8(data)?)
+        } else {
+            Err(Error::Unsupported)
+        }
+    }
+    /// Method to list the MIME types currently offered by the clipboard
+    fn available_formats(&mut self) -> Result<Vec<String>> {
+        Err(Error::Unsupported)
+    }
+    /// Method to set the clipboard contents to several representations
+    /// at once, given as pairs of MIME type and data
+    ///
+    /// Paste targets pick the format they understand. Backends that can
+    /// only hold plain text store the first plain text format, and
+    /// return [`Error::Unsupported`] if there is none.
+    fn set_formats(&mut self, formats: Vec<(String, Vec<u8>)>) -> Result<()> {
+        match formats.into_iter().find(|(mime, _)| mime::is_text(mime)) {
+            Some((mime, data)) => self.set_bytes(&mime, data),
+            None => Err(Error::Unsupported),
+        }
+    }
+    /// Method to get an image from the clipboard
+    ///
+    /// Reads the `image/png` format, and returns
+    /// [`Error::FormatUnavailable`] when the clipboard holds no image,
+    /// e.g. only text.
+    fn get_image(&mut self) -> Result<ImageData> {
+        ImageData::from_png(&self.get_bytes(mime::PNG)?)
+    }
+    /// Method to set the clipboard contents to an image
+    ///
+    /// The image is offered in the `image/png` format.
+    fn set_image(&mut self, image: ImageData) -> Result<()> {
+        self.set_bytes(mime::PNG, image.to_png()?)
+    }
+    /// Method to get the clipboard contents as HTML
+    ///
+    /// Prefers the `text/html` format, and falls back to the plain text
+    /// contents, escaped as HTML, when the clipboard holds no HTML.
+    fn get_html(&mut self) -> Result<String> {
+        match self.get_bytes(mime::HTML) {
+            Ok(contents) => html::decode(contents),
+            Err(Error::FormatUnavailable) => Ok(html::escape(&self.get_contents()?)),
+            Err(e) => Err(e),
+        }
+    }
+    /// Method to set the clipboard contents to HTML
+    ///
+    /// The HTML is offered as `text/html` alongside a plain text
+    /// representation for applications that cannot paste HTML:
+    /// `alt_text` if given, otherwise the HTML with its markup removed.
+    /// Backends that only hold plain text store just the plain text.
+    fn set_html(&mut self, html: String, alt_text: Option<String>) -> Result<()> {
+        let alt_text = alt_text.unwrap_or_else(|| html::to_plain_text(&html));
+        self.set_formats(vec![
+            (mime::HTML.to_string(), html.into_bytes()),
+            (mime::TEXT.to_string(), alt_text.into_bytes()),
+        ])
+    }
+    /// Method to get the list of files on the clipboard
+    ///
+    /// Reads the GNOME file list format, or `text/uri-list` together
+    /// with the KDE cut marker. URIs that are not local files are
+    /// skipped.
+    fn get_files(&mut self) -> Result<(Vec<PathBuf>, Operation)> {
+        match self.get_bytes(mime::GNOME_COPIED_FILES) {
+            Ok(contents) => return files::parse_gnome_copied_files(&contents),
+            Err(Error::FormatUnavailable) => (),
+            Err(e) => return Err(e),
+        }
+
+        let paths = files::parse_uri_list(&self.get_bytes(mime::URI_LIST)?)?;
+        let operation = match self.get_bytes(mime::KDE_CUT_SELECTION) {
+            Ok(contents) if contents.starts_with(b"1") => Operation::Cut,
+            _ => Operation::Copy,
+        };
+
+        Ok((paths, operation))
+    }
+    /// Method to put a list of files on the clipboard, to be pasted
+    /// into a file manager
+    ///
+    /// The files are offered as `text/uri-list`, in the GNOME and KDE
+    /// formats that carry the operation, and as plain text paths.
+    /// Relative paths are resolved against the current directory.
+    fn set_files(&mut self, paths: &[PathBuf], operation: Operation) -> Result<()> {
+        self.set_formats(files::to_formats(paths, operation)?)
+    }
+}
+
+/// Implements the methods of [`ClipboardOps`] by calling those of the
+/// clipboard `$inner` evaluates to, given `self` as `$this`
+macro_rules! forward_clipboard {
+    (|$this:ident| $inner:expr) => {
+        fn get_contents(&mut self) -> $crate::Result<String> {
+            let $this = self;
+            $inner.get_contents()
+        }
+        fn set_contents(&mut self, content: String) -> $crate::Result<()> {
+            let $this = self;
+            $inner.set_contents(content)
+        }
+        fn clear(&mut self) -> $crate::Result<()> {
+            let $this = self;
+            $inner.clear()
+        }
+        fn get_contents_with_deadline(
+            &mut self,
+            deadline: std::time::Instant,
+        ) -> $crate::Result<String> {
+            let $this = self;
+            $inner.get_contents_with_deadline(deadline)
+        }
+        fn set_contents_with_deadline(
+            &mut self,
+            content: String,
+            deadline: std::time::Instant,
+        ) -> $crate::Result<()> {
+            let $this = self;
+            $inner.set_contents_with_deadline(content, deadline)
+        }
+        fn clear_with_deadline(&mut self, deadline: std::time::Instant) -> $crate::Result<()> {
+            let $this = self;
+            $inner.clear_with_deadline(deadline)
+        }
+        fn get_bytes(&mut self, mime: &str) -> $crate::Result<Vec<u8>> {
+            let $this = self;
+            $inner.get_bytes(mime)
+        }
+        fn set_bytes(&mut self, mime: &str, data: Vec<u8>) -> $crate::Result<()> {
+            let $this = self;
+            $inner.set_bytes(mime, data)
+        }
+        fn available_formats(&mut self) -> $crate::Result<Vec<String>> {
+            let $this = self;
+            $inner.available_formats()
+        }
+        fn set_formats(&mut self, formats: Vec<(String, Vec<u8>)>) -> $crate::Result<()> {
+            let $this = self;
+            $inner.set_formats(formats)
+        }
+        fn get_image(&mut self) -> $crate::Result<$crate::ImageData> {
+            let $this = self;
+            $inner.get_image()
+        }
+        fn set_image(&mut self, image: $crate::ImageData) -> $crate::Result<()> {
+            let $this = self;
+            $inner.set_image(image)
+        }
+        fn get_html(&mut self) -> $crate::Result<String> {
+            let $this = self;
+            $inner.get_html()
+        }
+        fn set_html(&mut self, html: String, alt_text: Option<String>) -> $crate::Result<()> {
+            let $this = self;
+            $inner.set_html(html, alt_text)
+        }
+        fn get_files(&mut self) -> $crate::Result<(Vec<std::path::PathBuf>, $crate::Operation)> {
+            let $this = self;
+            $inner.get_files()
+        }
+        fn set_files(
+            &mut self,
+            paths: &[std::path::PathBuf],
+            operation: $crate::Operation,
+        ) -> $crate::Result<()> {
+            let $this = self;
+            $inner.set_files(paths, operation)
+        }
+    };
+}
+
+pub(crate) use forward_clipboard;
+
+impl<T> ClipboardOps for Box<T>
+where
+    T: ClipboardOps + ?Sized,
+{
+    forward_clipboard!(|this| (**this));
+}
+
+impl<T> ClipboardOps for &mut T
+where
+    T: ClipboardOps + ?Sized,
+{
+    forward_clipboard!(|this| (**this));
+}
+
+/// Locks the clipboard for each operation. Once an operation panicked
+/// while holding the lock, the clipboard may be left in any state, so
+/// every further operation fails with `Error::Backend`.
+impl<T> ClipboardOps for Arc<Mutex<T>>
+where
+    T: ClipboardOps + ?Sized,
+{
+    forward_clipboard!(|this| this
+        .lock()
+        .map_err(|_| Error::Backend("clipboard context lock poisoned".into()))?);
+}
+
+impl<T> ClipboardProvider for Box<T>
+where
+    T: ClipboardProvider,
+{
+    fn new() -> Result<Box<T>> {
+        Ok(Box::new(T::new()?))
+    }
+}
+
+impl<T> ClipboardProvider for Arc<Mutex<T>>
+where
+    T: ClipboardProvider,
+{
+    fn new() -> Result<Arc<Mutex<T>>> {
+        Ok(Arc::new(Mutex::new(T::new()?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_clipboard::MockClipboardContext;
+
+    fn copy(clipboard: &mut dyn ClipboardOps, content: &str) {
+        clipboard.set_contents(content.to_owned()).unwrap();
+    }
+
+    #[test]
+    fn forwarding_impls() {
+        let mock = MockClipboardContext::new().unwrap();
+
+        let mut boxed: Box<dyn ClipboardOps> = Box::new(mock.clone());
+        copy(&mut boxed, "boxed");
+        assert_eq!(mock.clone().get_contents().unwrap(), "boxed");
+
+        let mut inner = mock.clone();
+        copy(&mut &mut inner, "borrowed");
+        assert_eq!(boxed.get_contents().unwrap(), "borrowed");
+
+        let shared = Arc::new(Mutex::new(mock.clone()));
+        let mut other = Arc::clone(&shared);
+        copy(&mut other, "shared");
+        assert_eq!(shared.lock().unwrap().get_contents().unwrap(), "shared");
+
+        let mut created = <Arc<Mutex<MockClipboardContext>> as ClipboardProvider>::new().unwrap();
+        assert_eq!(created.get_contents().unwrap(), "");
+
+        let poisoned = Arc::clone(&shared);
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoned.lock().unwrap();
+            panic!("poisoning the lock");
+        })
+        .join();
+        assert!(matches!(other.get_contents(), Err(Error::Backend(_))));
+    }
 }
@@ -0,0 +1,266 @@
+//! Deadlines for the blocking calls of the Linux backends.
+
+use crate::{Error, Result};
+use std::io::{self, Read};
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long an operation may take unless configured otherwise
+pub(crate) const TIMEOUT: Duration = Duration::from_secs(3);
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs blocking calls on a thread of its own, so that callers can stop
+/// waiting for them at a deadline.
+///
+/// Calls run one at a time, in the order they were made. A call whose
+/// caller gave up before it started is skipped, and the result of one
+/// that finishes after its caller gave up is handed to the `late`
+/// handler given with it, which can undo its effect.
+pub(crate) struct Worker {
+    jobs: Mutex<Option<mpsc::Sender<Job>>>,
+}
+
+enum Outcome<T> {
+    Pending,
+    Done(Result<T>),
+    Abandoned,
+}
+
+/// A call shared between its caller and the worker thread
+struct Call<T> {
+    outcome: Mutex<Outcome<T>>,
+    finished: Condvar,
+}
+
+impl Worker {
+    /// Creates a worker. Its thread is started by the first call.
+    pub fn new() -> Worker {
+        Worker {
+            jobs: Mutex::new(None),
+        }
+    }
+
+    /// Runs `f` on the worker thread and waits for its result until
+    /// `deadline`, failing with `Error::Timeout` after that.
+    pub fn run_until<T, F, L>(&self, deadline: Instant, f: F, late: L) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+        L: FnOnce(Result<T>) + Send + 'static,
+    {
+        let call = Arc::new(Call {
+            outcome: Mutex::new(Outcome::Pending),
+            finished: Condvar::new(),
+        });
+
+        let shared = Arc::clone(&call);
+        self.send(Box::new(move || {
+            if let Outcome::Abandoned = *lock(&shared.outcome) {
+                return;
+            }
+
+            let result = panic::catch_unwind(AssertUnwindSafe(f))
+                .unwrap_or_else(|_| Err(Error::Backend("clipboard operation panicked".into())));
+
+            let mut outcome = lock(&shared.outcome);
+            if let Outcome::Abandoned = *outcome {
+                drop(outcome);
+                late(result);
+            } else {
+                *outcome = Outcome::Done(result);
+                shared.finished.notify_one();
+            }
+        }))?;
+
+        let mut outcome = lock(&call.outcome);
+        loop {
+            match mem::replace(&mut *outcome, Outcome::Pending) {
+                Outcome::Done(result) => return result,
+                pending => *outcome = pending,
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                *outcome = Outcome::Abandoned;
+                return Err(Error::Timeout);
+            }
+            outcome = call
+                .finished
+                .wait_timeout(outcome, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    /// Queues `job`, starting the thread if it isn't running.
+    fn send(&self, job: Job) -> Result<()> {
+        let mut jobs = lock(&self.jobs);
+        let job = match &*jobs {
+            Some(sender) => match sender.send(job) {
+                Ok(()) => return Ok(()),
+                Err(mpsc::SendError(job)) => job,
+            },
+            None => job,
+        };
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        thread::Builder::new()
+            .name("cli-clipboard".to_owned())
+            .spawn(move || receiver.into_iter().for_each(|job| job()))?;
+        let _ = sender.send(job);
+        *jobs = Some(sender);
+        Ok(())
+    }
+}
+
+/// Locks `mutex`. Nothing panics while the worker's locks are held, so
+/// they are never poisoned.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A pipe or similar that can be waited on
+pub(crate) trait Source: Read + AsRawFd {}
+
+impl<T: Read + AsRawFd> Source for T {}
+
+/// Reads `reader` to the end, failing once `deadline` passes.
+pub(crate) fn read_to_end<R>(reader: &mut R, deadline: Instant) -> Result<Vec<u8>>
+where
+    R: Read + AsRawFd,
+{
+    let mut contents = read_all_to_end(&mut [reader], deadline)?;
+    Ok(contents.remove(0))
+}
+
+/// Reads several readers to the end at once, failing once `deadline`
+/// passes. Unlike reading them one after the other, this doesn't stall
+/// a writer that fills one pipe while another one is read.
+pub(crate) fn read_all_to_end(
+    readers: &mut [&mut dyn Source],
+    deadline: Instant,
+) -> Result<Vec<Vec<u8>>> {
+    let mut contents = vec![Vec::new(); readers.len()];
+    let mut open = vec![true; readers.len()];
+    let mut buffer = [0; 8192];
+
+    while open.contains(&true) {
+        // Negative descriptors are ignored by poll
+        let mut pollfds: Vec<libc::pollfd> = readers
+            .iter()
+            .zip(&open)
+            .map(|(reader, &open)| libc::pollfd {
+                fd: if open { reader.as_raw_fd() } else { -1 },
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+
+        let now = Instant::now();
+        if now >= deadline || !poll(&mut pollfds, deadline - now)? {
+            return Err(Error::Timeout);
+        }
+
+        for (i, pollfd) in pollfds.iter().enumerate() {
+            if pollfd.revents == 0 {
+                continue;
+            }
+            match readers[i].read(&mut buffer) {
+                Ok(0) => open[i] = false,
+                Ok(len) => contents[i].extend_from_slice(&buffer[..len]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    Ok(contents)
+}
+
+/// Waits up to `timeout` for `fd` to become readable.
+pub(crate) fn wait_readable(fd: RawFd, timeout: Duration) -> io::Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    poll(std::slice::from_mut(&mut pollfd), timeout)
+}
+
+/// Waits up to `timeout` for any of `pollfds` to become ready.
+fn poll(pollfds: &mut [libc::pollfd], timeout: Duration) -> io::Result<bool> {
+    // Round up so that a timeout below a millisecond still waits
+    let millis = timeout.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128);
+    let count = pollfds.len() as libc::nfds_t;
+    match unsafe { libc::poll(pollfds.as_mut_ptr(), count, millis as libc::c_int) } {
+        -1 => match io::Error::last_os_error() {
+            e if e.kind() == io::ErrorKind::Interrupted => Ok(false),
+            e => Err(e),
+        },
+        n => Ok(n > 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::unix::io::FromRawFd;
+
+    #[test]
+    fn slow_calls_time_out() {
+        let worker = Worker::new();
+        let (late_sender, late_results) = mpsc::channel();
+
+        let deadline = Instant::now() + Duration::from_millis(20);
+        let result = worker.run_until(
+            deadline,
+            || {
+                thread::sleep(Duration::from_millis(200));
+                Ok(1)
+            },
+            move |late| late_sender.send(late).unwrap(),
+        );
+        assert!(matches!(result, Err(Error::Timeout)));
+
+        // Queued behind the slow call, and skipped once given up on
+        let deadline = Instant::now() + Duration::from_millis(20);
+        let skipped = worker.run_until(deadline, || -> Result<()> { panic!("ran") }, |_| ());
+        assert!(matches!(skipped, Err(Error::Timeout)));
+
+        let late = late_results.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(late.unwrap(), 1);
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        assert_eq!(worker.run_until(deadline, || Ok(2), |_| ()).unwrap(), 2);
+        let panicked = worker.run_until(deadline, || -> Result<()> { panic!() }, |_| ());
+        assert!(matches!(panicked, Err(Error::Backend(_))));
+        assert_eq!(worker.run_until(deadline, || Ok(3), |_| ()).unwrap(), 3);
+    }
+
+    #[test]
+    fn stalled_pipes_time_out() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut reader = unsafe { File::from_raw_fd(fds[0]) };
+        let mut writer = unsafe { File::from_raw_fd(fds[1]) };
+
+        writer.write_all(b"partial").unwrap();
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert!(matches!(
+            read_to_end(&mut reader, deadline),
+            Err(Error::Timeout)
+        ));
+
+        writer.write_all(b"done").unwrap();
+        drop(writer);
+        let deadline = Instant::now() + Duration::from_secs(1);
+        assert_eq!(read_to_end(&mut reader, deadline).unwrap(), b"done");
+    }
+}
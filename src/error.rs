@@ -0,0 +1,274 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::string::FromUtf8Error;
+
+/// Result type returned by every clipboard operation in this crate
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Boxed underlying error carried by some [`Error`] variants
+pub type BoxedError = Box<dyn StdError + Send + Sync>;
+
+/// Errors returned by clipboard operations
+///
+/// The variants are stable across platforms so callers can react to
+/// specific failures. Backend errors are kept as the [`source`] of the
+/// variant they were mapped to.
+///
+/// [`source`]: std::error::Error::source
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// No display server, compositor or clipboard service could be reached
+    NoDisplay(BoxedError),
+    /// The clipboard is empty
+    ClipboardEmpty,
+    /// The clipboard does not offer the requested format
+    FormatUnavailable,
+    /// The clipboard backend does not support the requested operation or format
+    Unsupported,
+    /// The clipboard contents were not valid UTF-8
+    NotUtf8(FromUtf8Error),
+    /// The clipboard owner did not answer in time
+    Timeout,
+    /// The Wayland compositor does not implement the data-control protocol
+    DataControlUnsupported,
+    /// Reading or writing clipboard data failed
+    Io(io::Error),
+    /// Image data could not be decoded or encoded
+    Image(BoxedError),
+    /// Any other failure reported by the platform clipboard
+    Backend(BoxedError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            NoDisplay(_) => write!(f, "couldn't connect to the clipboard"),
+            ClipboardEmpty => write!(f, "the clipboard is empty"),
+            FormatUnavailable => write!(f, "the clipboard does not offer the requested format"),
+            Unsupported => write!(f, "operation not supported by this clipboard"),
+            NotUtf8(_) => write!(f, "the clipboard contents are not valid UTF-8"),
+            Timeout => write!(f, "timed out waiting for the clipboard"),
+            DataControlUnsupported => write!(
+                f,
+                "the Wayland compositor does not support the data-control protocol"
+            ),
+            Io(_) => write!(f, "couldn't transfer clipboard data"),
+            Image(_) => write!(f, "invalid image data"),
+            Backend(_) => write!(f, "clipboard error"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        use self::Error::*;
+        match self {
+            NoDisplay(e) | Image(e) | Backend(e) => Some(e.as_ref()),
+            NotUtf8(e) => Some(e),
+            Io(e) => Some(e),
+            ClipboardEmpty | FormatUnavailable | Unsupported | Timeout | DataControlUnsupported => {
+                None
+            }
+        }
+    }
+}
+
+impl Error {
+    /// Whether the clipboard service couldn't be reached or the
+    /// connection to it was lost, leaving the context that failed unusable
+    #[cfg(not(target_os = "macos"))]
+    pub(crate) fn is_disconnect(&self) -> bool {
+        match self {
+            Error::NoDisplay(_) => true,
+            #[cfg(all(
+                unix,
+                not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+            ))]
+            Error::Backend(err) => linux::is_connection_error(err.as_ref()),
+            _ => false,
+        }
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(err: FromUtf8Error) -> Error {
+        Error::NotUtf8(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+))]
+mod linux {
+    use super::Error;
+    use std::error::Error as StdError;
+    use wl_clipboard_rs::{copy, paste, utils};
+    use x11_clipboard_crate::error::Error as X11Error;
+    use x11rb::errors::{ConnectionError, ReplyError, ReplyOrIdError};
+
+    /// Name of the wlroots data-control protocol global
+    const DATA_CONTROL: &str = "zwlr_data_control_manager_v1";
+
+    impl From<paste::Error> for Error {
+        fn from(err: paste::Error) -> Error {
+            match err {
+                paste::Error::NoSeats | paste::Error::ClipboardEmpty => Error::ClipboardEmpty,
+                paste::Error::NoMimeType => Error::FormatUnavailable,
+                paste::Error::WaylandConnection(_) => Error::NoDisplay(Box::new(err)),
+                paste::Error::MissingProtocol { name, .. } if name == DATA_CONTROL => {
+                    Error::DataControlUnsupported
+                }
+                err => Error::Backend(Box::new(err)),
+            }
+        }
+    }
+
+    impl From<copy::Error> for Error {
+        fn from(err: copy::Error) -> Error {
+            match err {
+                copy::Error::WaylandConnection(_) => Error::NoDisplay(Box::new(err)),
+                copy::Error::MissingProtocol { name, .. } if name == DATA_CONTROL => {
+                    Error::DataControlUnsupported
+                }
+                err => Error::Backend(Box::new(err)),
+            }
+        }
+    }
+
+    impl From<utils::PrimarySelectionCheckError> for Error {
+        fn from(err: utils::PrimarySelectionCheckError) -> Error {
+            use utils::PrimarySelectionCheckError::*;
+            match err {
+                WaylandConnection(_) => Error::NoDisplay(Box::new(err)),
+                MissingProtocol { name, .. } if name == DATA_CONTROL => {
+                    Error::DataControlUnsupported
+                }
+                err => Error::Backend(Box::new(err)),
+            }
+        }
+    }
+
+    impl From<X11Error> for Error {
+        fn from(err: X11Error) -> Error {
+            match err {
+                X11Error::XcbConnect(_) => Error::NoDisplay(Box::new(err)),
+                X11Error::Timeout => Error::Timeout,
+                err => Error::Backend(Box::new(err)),
+            }
+        }
+    }
+
+    /// Whether `err` is the X11 connection failing
+    pub(super) fn is_connection_error(err: &(dyn StdError + 'static)) -> bool {
+        matches!(
+            err.downcast_ref::<X11Error>(),
+            Some(
+                X11Error::XcbConnection(_)
+                    | X11Error::XcbReply(ReplyError::ConnectionError(_))
+                    | X11Error::XcbReplyOrId(ReplyOrIdError::ConnectionError(_))
+            )
+        )
+    }
+
+    impl From<ConnectionError> for Error {
+        fn from(err: ConnectionError) -> Error {
+            X11Error::from(err).into()
+        }
+    }
+
+    impl From<ReplyError> for Error {
+        fn from(err: ReplyError) -> Error {
+            X11Error::from(err).into()
+        }
+    }
+}
+
+#[cfg(windows)]
+impl From<clipboard_win::SystemError> for Error {
+    fn from(err: clipboard_win::SystemError) -> Error {
+        Error::Backend(Box::new(err))
+    }
+}
+
+#[cfg(target_os = "android")]
+impl From<jni::errors::Error> for Error {
+    fn from(err: jni::errors::Error) -> Error {
+        Error::Backend(Box::new(err))
+    }
+}
+
+#[cfg(target_os = "android")]
+impl From<std::ffi::IntoStringError> for Error {
+    fn from(err: std::ffi::IntoStringError) -> Error {
+        Error::Backend(Box::new(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn causes_are_only_sources() {
+        let err = Error::Backend("xclip failed".into());
+        assert_eq!(err.to_string(), "clipboard error");
+        assert_eq!(err.source().unwrap().to_string(), "xclip failed");
+
+        let err = Error::NoDisplay("no $DISPLAY".into());
+        assert_eq!(err.to_string(), "couldn't connect to the clipboard");
+        assert_eq!(err.source().unwrap().to_string(), "no $DISPLAY");
+    }
+
+    #[test]
+    fn utf8_errors_are_chained() {
+        let err: Error = String::from_utf8(vec![0xff]).unwrap_err().into();
+        assert!(matches!(err, Error::NotUtf8(_)));
+        assert!(err.source().is_some());
+    }
+
+    #[cfg(all(
+        unix,
+        not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+    ))]
+    #[test]
+    fn wayland_errors_are_mapped() {
+        use wl_clipboard_rs::paste;
+
+        let err: Error = paste::Error::MissingProtocol {
+            name: "zwlr_data_control_manager_v1",
+            version: 1,
+        }
+        .into();
+        assert!(matches!(err, Error::DataControlUnsupported));
+
+        let err: Error = paste::Error::ClipboardEmpty.into();
+        assert!(matches!(err, Error::ClipboardEmpty));
+    }
+
+    #[cfg(all(
+        unix,
+        not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+    ))]
+    #[test]
+    fn connection_errors_are_disconnects() {
+        use x11rb::errors::{ConnectionError, ReplyError};
+
+        let err: Error = ReplyError::ConnectionError(ConnectionError::UnknownError).into();
+        assert!(matches!(err, Error::Backend(_)));
+        assert!(err.is_disconnect());
+
+        assert!(Error::NoDisplay("gone".into()).is_disconnect());
+        assert!(!Error::Timeout.is_disconnect());
+        assert!(!Error::Backend("other".into()).is_disconnect());
+    }
+}
@@ -0,0 +1,272 @@
+//! Clipboard that uses the first of several providers that works.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use cli_clipboard::fallback_clipboard::FallbackClipboard;
+//! use cli_clipboard::file_clipboard::FileClipboardContext;
+//! use cli_clipboard::osc52_clipboard::Osc52ClipboardContext;
+//! use cli_clipboard::wayland_clipboard::WaylandClipboardContext;
+//! use cli_clipboard::x11_clipboard::{Clipboard, X11ClipboardContext};
+//! use cli_clipboard::ClipboardOps;
+//!
+//! let mut ctx = FallbackClipboard::builder()
+//!     .provider::<WaylandClipboardContext>("wayland")
+//!     .provider::<X11ClipboardContext>("x11")
+//!     .provider::<Osc52ClipboardContext>("osc52")
+//!     .provider_with("file", || {
+//!         Ok(FileClipboardContext::<Clipboard>::with_path("/tmp/clipboard"))
+//!     })
+//!     .build()
+//!     .unwrap();
+//!
+//! for failure in ctx.failures() {
+//!     eprintln!("skipped {}: {}", failure.name(), failure.error());
+//! }
+//! let name = ctx.provider_name().to_owned();
+//! ctx.set_contents(format!("copied with {}", name)).unwrap();
+//! ```
+
+use crate::common::{forward_clipboard, ClipboardOps, ClipboardProvider};
+use crate::{Error, Result};
+use std::error::Error as StdError;
+use std::fmt;
+
+type Factory = Box<dyn FnOnce() -> Result<Box<dyn ClipboardOps + Send>>>;
+
+/// A provider that could not be created, with the reason
+#[derive(Debug)]
+pub struct ProviderFailure {
+    name: String,
+    error: Error,
+}
+
+impl ProviderFailure {
+    /// Returns the name the provider was added with.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the error creating the provider failed with.
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+}
+
+/// Error for a [`FallbackClipboard`] none of whose providers could be
+/// created, carried by `Error::NoDisplay`
+#[derive(Debug)]
+pub struct FallbackError {
+    failures: Vec<ProviderFailure>,
+}
+
+impl FallbackError {
+    /// Returns why each provider failed, in the order they were tried.
+    pub fn failures(&self) -> &[ProviderFailure] {
+        &self.failures
+    }
+}
+
+impl fmt::Display for FallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no clipboard provider could be used")?;
+        for (i, failure) in self.failures.iter().enumerate() {
+            let separator = if i == 0 { ": " } else { "; " };
+            write!(f, "{}{}: {}", separator, failure.name, failure.error)?;
+            // The failures can't all be the source, so their causes are
+            // part of the message
+            let mut cause = failure.error.source();
+            while let Some(error) = cause {
+                write!(f, ": {}", error)?;
+                cause = error.source();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StdError for FallbackError {}
+
+/// Clipboard that uses the first provider of an ordered list that can
+/// be created.
+///
+/// The providers are tried when the clipboard is built, and the errors
+/// of the ones that failed are kept. If none can be created, building
+/// fails with `Error::NoDisplay` carrying a [`FallbackError`].
+///
+/// Any clipboard that can be sent to other threads can take part,
+/// including the caller's own and boxed `dyn ClipboardOps + Send`s
+/// chosen at runtime, so the clipboard itself can be moved between
+/// threads or wrapped in an `AsyncClipboardContext`.
+pub struct FallbackClipboard {
+    name: String,
+    provider: Box<dyn ClipboardOps + Send>,
+    failures: Vec<ProviderFailure>,
+}
+
+impl FallbackClipboard {
+    /// Returns a builder to add the providers to.
+    pub fn builder() -> FallbackBuilder {
+        FallbackBuilder {
+            factories: Vec::new(),
+        }
+    }
+
+    /// Returns the name of the provider in use.
+    pub fn provider_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns why each provider tried before the one in use failed.
+    pub fn failures(&self) -> &[ProviderFailure] {
+        &self.failures
+    }
+
+    /// Returns the name of the provider in use and the provider.
+    #[cfg(all(
+        unix,
+        not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+    ))]
+    pub(crate) fn into_provider(self) -> (String, Box<dyn ClipboardOps + Send>) {
+        (self.name, self.provider)
+    }
+}
+
+impl ClipboardProvider for FallbackClipboard {
+    /// Tries the backends `Backend::Auto` of `LinuxClipboardContext`
+    /// tries for the regular clipboard with default settings: Wayland,
+    /// X11, clipboard programs, then tmux if `$TMUX` is set and GNU
+    /// screen if `$STY` is. A file is not tried, since contents kept
+    /// there can't be pasted elsewhere; add it with
+    /// [`builder`](Self::builder) if wanted. On other platforms there is
+    /// only the platform's clipboard.
+    fn new() -> Result<FallbackClipboard> {
+        #[cfg(all(
+            unix,
+            not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+        ))]
+        let builder = crate::ClipboardContext::builder().auto_chain();
+
+        #[cfg(not(all(
+            unix,
+            not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+        )))]
+        let builder = FallbackClipboard::builder().provider::<crate::ClipboardContext>("default");
+
+        builder.build()
+    }
+}
+
+impl ClipboardOps for FallbackClipboard {
+    forward_clipboard!(|this| this.provider);
+}
+
+/// Collects the providers of a [`FallbackClipboard`], created by
+/// [`FallbackClipboard::builder`].
+pub struct FallbackBuilder {
+    factories: Vec<(String, Factory)>,
+}
+
+impl FallbackBuilder {
+    /// Adds a provider created with its `new` function.
+    pub fn provider<P>(self, name: impl Into<String>) -> Self
+    where
+        P: ClipboardProvider + Send + 'static,
+    {
+        self.provider_with(name, P::new)
+    }
+
+    /// Adds a clipboard created by `create`, which is only called if the
+    /// providers before it fail.
+    pub fn provider_with<C, F>(mut self, name: impl Into<String>, create: F) -> Self
+    where
+        C: ClipboardOps + Send + 'static,
+        F: FnOnce() -> Result<C> + 'static,
+    {
+        let factory: Factory = Box::new(move || {
+            let provider = create()?;
+            Ok(Box::new(provider) as Box<dyn ClipboardOps + Send>)
+        });
+        self.factories.push((name.into(), factory));
+        self
+    }
+
+    /// Creates the first provider that can be created.
+    pub fn build(self) -> Result<FallbackClipboard> {
+        let mut failures = Vec::new();
+
+        for (name, create) in self.factories {
+            match create() {
+                Ok(provider) => {
+                    return Ok(FallbackClipboard {
+                        name,
+                        provider,
+                        failures,
+                    })
+                }
+                Err(error) => failures.push(ProviderFailure { name, error }),
+            }
+        }
+
+        Err(Error::NoDisplay(Box::new(FallbackError { failures })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_clipboard::MockClipboardContext;
+
+    #[test]
+    fn uses_the_first_working_provider() {
+        let mock = MockClipboardContext::new().unwrap();
+        let shared = mock.clone();
+
+        let mut ctx = FallbackClipboard::builder()
+            .provider_with("broken", || -> Result<MockClipboardContext> {
+                Err(Error::Unsupported)
+            })
+            .provider_with("mock", move || Ok(shared))
+            .provider_with("unused", || -> Result<MockClipboardContext> {
+                panic!("created a provider after a working one")
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(ctx.provider_name(), "mock");
+        assert_eq!(ctx.failures().len(), 1);
+        assert_eq!(ctx.failures()[0].name(), "broken");
+        assert!(matches!(ctx.failures()[0].error(), Error::Unsupported));
+
+        ctx.set_html("<i>hi</i>".to_owned(), None).unwrap();
+        assert_eq!(mock.clone().get_contents().unwrap(), "hi");
+    }
+
+    #[test]
+    fn is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<FallbackClipboard>();
+    }
+
+    #[test]
+    fn reports_every_failure() {
+        let error = FallbackClipboard::builder()
+            .provider_with("a", || -> Result<MockClipboardContext> {
+                Err(Error::Timeout)
+            })
+            .provider_with("b", || -> Result<MockClipboardContext> {
+                Err(Error::Unsupported)
+            })
+            .build()
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            error.source().unwrap().to_string(),
+            "no clipboard provider could be used: a: timed out waiting for the clipboard; \
+             b: operation not supported by this clipboard"
+        );
+        let fallback = error.source().unwrap().downcast_ref::<FallbackError>();
+        assert_eq!(fallback.unwrap().failures().len(), 2);
+    }
+}
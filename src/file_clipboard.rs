@@ -0,0 +1,330 @@
+//! Clipboard stored in a file, for machines without a display server.
+
+use crate::common::*;
+use crate::deadline::TIMEOUT;
+use crate::x11_clipboard::{Clipboard, Selection};
+use crate::{mime, Error, Result};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// First line of a clipboard file, naming the layout of the rest
+const HEADER: &[u8] = b"cli-clipboard 1\n";
+/// Interval between attempts to lock a file locked by another process
+const LOCK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Clipboard that keeps its contents in a file.
+///
+/// This lets processes on headless machines and in containers, where
+/// there is neither a Wayland compositor nor an X server, share a
+/// clipboard. By default the file is
+/// `$XDG_RUNTIME_DIR/cli-clipboard/clipboard`, or `primary` for the
+/// primary selection, falling back to a private directory under the
+/// system's temporary directory if `$XDG_RUNTIME_DIR` is unset.
+///
+/// Contents are stored with their MIME types, so every format survives
+/// the round trip. Concurrent processes are serialized with `flock`.
+///
+/// `LinuxClipboardContext` only uses this provider when asked to, with
+/// `Backend::File`, or as the last resort of `Backend::Auto` with
+/// [`ClipboardBuilder::headless_fallback`](crate::linux_clipboard::ClipboardBuilder::headless_fallback)
+/// set.
+pub struct FileClipboardContext<S = Clipboard>
+where
+    S: Selection,
+{
+    path: PathBuf,
+    timeout: Duration,
+    selection: PhantomData<S>,
+}
+
+impl<S> ClipboardProvider for FileClipboardContext<S>
+where
+    S: Selection,
+{
+    /// Creates the directory of the default file if needed.
+    fn new() -> Result<FileClipboardContext<S>> {
+        let dir = runtime_dir()?;
+        let name = if S::is_primary() {
+            "primary"
+        } else {
+            "clipboard"
+        };
+        Ok(FileClipboardContext::with_path(dir.join(name)))
+    }
+}
+
+impl<S> ClipboardOps for FileClipboardContext<S>
+where
+    S: Selection,
+{
+    fn get_contents(&mut self) -> Result<String> {
+        self.get_contents_with_deadline(self.deadline())
+    }
+
+    fn set_contents(&mut self, data: String) -> Result<()> {
+        self.set_contents_with_deadline(data, self.deadline())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.clear_with_deadline(self.deadline())
+    }
+
+    /// Returns the first plain text format, or an empty string if the
+    /// clipboard is empty. Fails with `Error::Timeout` if another
+    /// process keeps the file locked until `deadline`.
+    fn get_contents_with_deadline(&mut self, deadline: Instant) -> Result<String> {
+        match self.load(mime::TEXT, deadline) {
+            Ok(contents) => Ok(String::from_utf8(contents)?),
+            Err(Error::ClipboardEmpty | Error::FormatUnavailable) => Ok("".to_string()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn set_contents_with_deadline(&mut self, data: String, deadline: Instant) -> Result<()> {
+        self.store(vec![(mime::TEXT.to_string(), data.into_bytes())], deadline)
+    }
+
+    fn clear_with_deadline(&mut self, deadline: Instant) -> Result<()> {
+        self.store(Vec::new(), deadline)
+    }
+
+    /// Plain text MIME types match any plain text format.
+    fn get_bytes(&mut self, mime: &str) -> Result<Vec<u8>> {
+        self.load(mime, self.deadline())
+    }
+
+    fn set_bytes(&mut self, mime: &str, data: Vec<u8>) -> Result<()> {
+        self.store(vec![(mime.to_owned(), data)], self.deadline())
+    }
+
+    fn available_formats(&mut self) -> Result<Vec<String>> {
+        let mut formats: Vec<String> = self
+            .read(self.deadline())?
+            .into_iter()
+            .map(|(format, _)| format)
+            .collect();
+
+        formats.sort();
+        formats.dedup();
+        Ok(formats)
+    }
+
+    fn set_formats(&mut self, formats: Vec<(String, Vec<u8>)>) -> Result<()> {
+        self.store(formats, self.deadline())
+    }
+}
+
+impl<S> FileClipboardContext<S>
+where
+    S: Selection,
+{
+    /// Uses the file at `path`, which is created when first written.
+    pub fn with_path(path: impl Into<PathBuf>) -> FileClipboardContext<S> {
+        FileClipboardContext {
+            path: path.into(),
+            timeout: TIMEOUT,
+            selection: PhantomData,
+        }
+    }
+
+    /// Returns the path of the clipboard file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Sets how long to wait for other processes to unlock the file
+    /// before failing with `Error::Timeout`. Defaults to 3 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn deadline(&self) -> Instant {
+        Instant::now() + self.timeout
+    }
+
+    fn load(&self, mime: &str, deadline: Instant) -> Result<Vec<u8>> {
+        let formats = self.read(deadline)?;
+        if formats.is_empty() {
+            return Err(Error::ClipboardEmpty);
+        }
+
+        formats
+            .into_iter()
+            .find(|(format, _)| format == mime || (mime::is_text(mime) && mime::is_text(format)))
+            .map(|(_, data)| data)
+            .ok_or(Error::FormatUnavailable)
+    }
+
+    fn read(&self, deadline: Instant) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        lock(&file, libc::LOCK_SH, deadline)?;
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        decode(&contents)
+    }
+
+    fn store(&self, formats: Vec<(String, Vec<u8>)>, deadline: Instant) -> Result<()> {
+        let contents = encode(&formats)?;
+
+        // Truncating before locking would pull the contents away from
+        // readers holding the lock.
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(&self.path)?;
+        lock(&file, libc::LOCK_EX, deadline)?;
+
+        file.set_len(0)?;
+        file.write_all(&contents)?;
+        Ok(())
+    }
+}
+
+/// Returns the private directory for the clipboard files of the user,
+/// creating it if needed.
+pub(crate) fn runtime_dir() -> Result<PathBuf> {
+    let dir = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("cli-clipboard"),
+        _ => env::temp_dir().join(format!("cli-clipboard-{}", unsafe { libc::getuid() })),
+    };
+
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)?;
+    // The temporary directory is shared, so someone else may have
+    // created the directory first.
+    if fs::metadata(&dir)?.uid() != unsafe { libc::getuid() } {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is owned by another user", dir.display()),
+        )
+        .into());
+    }
+
+    Ok(dir)
+}
+
+/// Locks `file` with `flock`, retrying until `deadline`. The lock is
+/// released when the file is closed.
+fn lock(file: &File, operation: libc::c_int, deadline: Instant) -> Result<()> {
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+            return Ok(());
+        }
+
+        let error = io::Error::last_os_error();
+        match error.kind() {
+            io::ErrorKind::WouldBlock if Instant::now() < deadline => thread::sleep(LOCK_INTERVAL),
+            io::ErrorKind::WouldBlock => return Err(Error::Timeout),
+            io::ErrorKind::Interrupted => (),
+            _ => return Err(error.into()),
+        }
+    }
+}
+
+/// Serializes formats as the header followed by, for every format, the
+/// MIME type and the data length on lines of their own, and the data.
+fn encode(formats: &[(String, Vec<u8>)]) -> Result<Vec<u8>> {
+    let mut contents = HEADER.to_vec();
+    for (mime, data) in formats {
+        if mime.contains('\n') {
+            return Err(Error::Unsupported);
+        }
+        contents.extend_from_slice(format!("{}\n{}\n", mime, data.len()).as_bytes());
+        contents.extend_from_slice(data);
+    }
+    Ok(contents)
+}
+
+fn decode(contents: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    // A file created but not yet written holds no formats
+    if contents.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let malformed = || Error::Backend("malformed clipboard file".into());
+    let mut rest = contents.strip_prefix(HEADER).ok_or_else(malformed)?;
+    let mut formats = Vec::new();
+
+    while !rest.is_empty() {
+        let mut line = || -> Result<String> {
+            let end = rest
+                .iter()
+                .position(|&b| b == b'\n')
+                .ok_or_else(malformed)?;
+            let line = String::from_utf8(rest[..end].to_vec()).map_err(|_| malformed())?;
+            rest = &rest[end + 1..];
+            Ok(line)
+        };
+        let mime = line()?;
+        let len: usize = line()?.parse().map_err(|_| malformed())?;
+
+        if rest.len() < len {
+            return Err(malformed());
+        }
+        formats.push((mime, rest[..len].to_vec()));
+        rest = &rest[len..];
+    }
+
+    Ok(formats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_round_trip() {
+        let formats = vec![
+            (mime::TEXT.to_string(), b"two\nlines".to_vec()),
+            (mime::PNG.to_string(), vec![0, b'\n', 255]),
+            (mime::HTML.to_string(), Vec::new()),
+        ];
+        assert_eq!(decode(&encode(&formats).unwrap()).unwrap(), formats);
+        assert_eq!(decode(b"").unwrap(), []);
+        assert!(decode(b"cli-clipboard 1\ntext/plain\n10\nshort").is_err());
+    }
+
+    #[test]
+    fn file_clipboard() {
+        let path = env::temp_dir().join(format!("cli-clipboard-test-{}", std::process::id()));
+        let mut ctx: FileClipboardContext = FileClipboardContext::with_path(&path);
+
+        assert_eq!(ctx.get_contents().unwrap(), "");
+        ctx.set_html("<b>bold</b>".to_string(), None).unwrap();
+        assert_eq!(ctx.available_formats().unwrap(), [mime::HTML, mime::TEXT]);
+        assert_eq!(ctx.get_contents().unwrap(), "bold");
+
+        // Another process holding the lock makes operations time out
+        let other = File::open(&path).unwrap();
+        lock(&other, libc::LOCK_EX, Instant::now()).unwrap();
+        let deadline = Instant::now() + Duration::from_millis(30);
+        assert!(matches!(
+            ctx.get_contents_with_deadline(deadline),
+            Err(Error::Timeout)
+        ));
+        drop(other);
+
+        ctx.clear().unwrap();
+        assert!(matches!(
+            ctx.get_bytes(mime::HTML),
+            Err(Error::ClipboardEmpty)
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{mime, Error, Result};
+use std::path::{Path, PathBuf};
+
+/// What the paste target should do with files on the clipboard
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// The files are copied
+    Copy,
+    /// The files are moved
+    Cut,
+}
+
+/// Builds the formats offered for a list of files: `text/uri-list`,
+/// the GNOME and KDE formats carrying the operation, and the plain
+/// paths for applications that only paste text.
+pub(crate) fn to_formats(
+    paths: &[PathBuf],
+    operation: Operation,
+) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut uris = Vec::with_capacity(paths.len());
+    let mut text = Vec::with_capacity(paths.len());
+    for path in paths {
+        let path = if path.is_absolute() {
+            path.clone()
+        } else {
+            std::env::current_dir()?.join(path)
+        };
+        uris.push(to_uri(&path));
+        text.push(path.to_string_lossy().into_owned());
+    }
+
+    let (gnome_operation, kde_cut) = match operation {
+        Operation::Copy => ("copy", "0"),
+        Operation::Cut => ("cut", "1"),
+    };
+
+    let mut uri_list = uris.join("\r\n");
+    uri_list.push_str("\r\n");
+    let gnome = format!("{}\n{}", gnome_operation, uris.join("\n"));
+
+    Ok(vec![
+        (mime::TEXT.to_string(), text.join("\n").into_bytes()),
+        (mime::URI_LIST.to_string(), uri_list.into_bytes()),
+        (mime::GNOME_COPIED_FILES.to_string(), gnome.into_bytes()),
+        (
+            mime::KDE_CUT_SELECTION.to_string(),
+            kde_cut.as_bytes().to_vec(),
+        ),
+    ])
+}
+
+/// Parses `x-special/gnome-copied-files`: the operation on the first
+/// line followed by one URI per line.
+pub(crate) fn parse_gnome_copied_files(data: &[u8]) -> Result<(Vec<PathBuf>, Operation)> {
+    let data = String::from_utf8(data.to_vec())?;
+    let mut lines = data.lines();
+
+    let operation = match lines.next().map(str::trim) {
+        Some("cut") => Operation::Cut,
+        _ => Operation::Copy,
+    };
+
+    Ok((lines.filter_map(from_uri).collect(), operation))
+}
+
+/// Parses `text/uri-list`, skipping comments and URIs that are not
+/// local files.
+pub(crate) fn parse_uri_list(data: &[u8]) -> Result<Vec<PathBuf>> {
+    let data = String::from_utf8(data.to_vec())?;
+
+    let paths: Vec<PathBuf> = data
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(from_uri)
+        .collect();
+
+    if paths.is_empty() {
+        Err(Error::FormatUnavailable)
+    } else {
+        Ok(paths)
+    }
+}
+
+fn to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path_bytes(path) {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            byte => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+fn from_uri(uri: &str) -> Option<PathBuf> {
+    let rest = uri.trim().strip_prefix("file://")?;
+    // Skip the host, which is empty or names the local machine
+    let path = &rest[rest.find('/')?..];
+
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+
+    Some(path_from_bytes(bytes))
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    std::ffi::OsString::from_vec(bytes).into()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    // file:///C:/dir/file
+    let path = path.to_string_lossy().replace('\\', "/");
+    format!("/{}", path.trim_start_matches('/')).into_bytes()
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    String::from_utf8_lossy(&bytes)
+        .trim_start_matches('/')
+        .to_string()
+        .into()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uri_round_trip() {
+        let path = PathBuf::from("/tmp/a dir/ünïcode%.txt");
+        let uri = to_uri(&path);
+        assert_eq!(uri, "file:///tmp/a%20dir/%C3%BCn%C3%AFcode%25.txt");
+        assert_eq!(from_uri(&uri), Some(path));
+        assert_eq!(
+            from_uri("file://localhost/etc/hosts"),
+            Some(PathBuf::from("/etc/hosts"))
+        );
+        assert_eq!(from_uri("https://example.com/"), None);
+    }
+
+    #[test]
+    fn file_formats() {
+        let paths = [PathBuf::from("/a"), PathBuf::from("/b c")];
+        let formats = to_formats(&paths, Operation::Cut).unwrap();
+
+        let format = |mime: &str| {
+            formats
+                .iter()
+                .find(|(format, _)| format == mime)
+                .map(|(_, data)| data.clone())
+                .unwrap()
+        };
+        assert_eq!(format(mime::URI_LIST), b"file:///a\r\nfile:///b%20c\r\n");
+        assert_eq!(format(mime::KDE_CUT_SELECTION), b"1");
+
+        let gnome = format(mime::GNOME_COPIED_FILES);
+        assert_eq!(gnome, b"cut\nfile:///a\nfile:///b%20c");
+        assert_eq!(
+            parse_gnome_copied_files(&gnome).unwrap(),
+            (paths.to_vec(), Operation::Cut)
+        );
+        assert_eq!(
+            parse_uri_list(b"# comment\r\nfile:///a\r\nfile:///b%20c\r\n").unwrap(),
+            paths.to_vec()
+        );
+    }
+}
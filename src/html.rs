@@ -0,0 +1,212 @@
+use crate::Result;
+
+/// Elements whose end starts a new line in the plain text rendering
+const BLOCK_ELEMENTS: [&str; 16] = [
+    "p",
+    "div",
+    "br",
+    "li",
+    "tr",
+    "table",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "pre",
+    "ul",
+    "ol",
+    "blockquote",
+];
+
+/// Elements whose contents are not displayed
+const HIDDEN_ELEMENTS: [&str; 4] = ["head", "script", "style", "title"];
+
+/// Decodes `text/html` clipboard data.
+///
+/// Most applications offer UTF-8, but some (notably Firefox on X11)
+/// offer UTF-16 with a byte order mark.
+pub(crate) fn decode(html: Vec<u8>) -> Result<String> {
+    match html.as_slice() {
+        [0xff, 0xfe, rest @ ..] => Ok(decode_utf16(rest, u16::from_le_bytes)),
+        [0xfe, 0xff, rest @ ..] => Ok(decode_utf16(rest, u16::from_be_bytes)),
+        _ => Ok(String::from_utf8(html)?),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> String {
+    let units = bytes.chunks_exact(2).map(|b| from_bytes([b[0], b[1]]));
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// Escapes plain text for inclusion in HTML.
+pub(crate) fn escape(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+/// Renders HTML as plain text by dropping markup, for use as the
+/// plain text alternative of copied HTML.
+///
+/// This is not a full HTML parser: block elements become line breaks,
+/// table cells are separated by tabs and whitespace is collapsed.
+pub(crate) fn to_plain_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut hidden: Option<String> = None;
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        if hidden.is_none() {
+            push_text(&mut text, &rest[..start]);
+        }
+
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => {
+                rest = "";
+                break;
+            }
+        };
+        let tag = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        if let Some(hidden_name) = &hidden {
+            if closing && *hidden_name == name {
+                hidden = None;
+            }
+        } else if HIDDEN_ELEMENTS.contains(&name.as_str()) && !closing {
+            hidden = Some(name);
+        } else if name == "br" || (closing && BLOCK_ELEMENTS.contains(&name.as_str())) {
+            push_break(&mut text, '\n');
+        } else if closing && (name == "td" || name == "th") {
+            push_break(&mut text, '\t');
+        }
+    }
+    if hidden.is_none() {
+        push_text(&mut text, rest);
+    }
+
+    text.trim().to_string()
+}
+
+/// Appends a text run, collapsing whitespace and decoding entities.
+fn push_text(text: &mut String, run: &str) {
+    let mut space = run.starts_with(char::is_whitespace);
+    for word in run.split_whitespace() {
+        if space && !text.is_empty() && !text.ends_with(char::is_whitespace) {
+            text.push(' ');
+        }
+        push_decoded(text, word);
+        space = true;
+    }
+    if run.ends_with(char::is_whitespace)
+        && !text.is_empty()
+        && !text.ends_with(char::is_whitespace)
+    {
+        text.push(' ');
+    }
+}
+
+fn push_break(text: &mut String, separator: char) {
+    while text.ends_with(' ') || (separator == '\n' && text.ends_with('\t')) {
+        text.pop();
+    }
+    text.push(separator);
+}
+
+/// Appends `word` with character references decoded.
+fn push_decoded(text: &mut String, word: &str) {
+    let mut rest = word;
+    while let Some(start) = rest.find('&') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                "nbsp" => '\u{a0}',
+                entity => {
+                    let code = match entity
+                        .strip_prefix("#x")
+                        .or_else(|| entity.strip_prefix("#X"))
+                    {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => entity.strip_prefix('#')?.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, end))
+        });
+
+        match decoded {
+            Some((c, end)) => {
+                text.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                text.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_from_html() {
+        assert_eq!(
+            to_plain_text("<p>Hello <b>world</b> &amp; friends</p><p>Second</p>"),
+            "Hello world & friends\nSecond"
+        );
+        assert_eq!(
+            to_plain_text(
+                "<table><tr><td>a</td><td>b</td></tr><tr><td>c</td><td>d</td></tr></table>"
+            ),
+            "a\tb\nc\td"
+        );
+        assert_eq!(
+            to_plain_text("<head><style>p { color: red }</style></head>x&#169;y"),
+            "x\u{a9}y"
+        );
+    }
+
+    #[test]
+    fn escapes_text() {
+        assert_eq!(escape("a < b & c"), "a &lt; b &amp; c");
+    }
+
+    #[test]
+    fn decodes_utf16() {
+        let html = [0xff, 0xfe, b'<', 0, b'b', 0, b'>', 0];
+        assert_eq!(decode(html.to_vec()).unwrap(), "<b>");
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{Error, Result};
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+use std::convert::TryFrom;
+
+/// An image on the clipboard as 8-bit RGBA pixels
+///
+/// `bytes` holds `width * height` pixels in row-major order, four bytes
+/// (red, green, blue, alpha) per pixel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImageData {
+    pub width: usize,
+    pub height: usize,
+    pub bytes: Vec<u8>,
+}
+
+impl ImageData {
+    /// Decodes a PNG image, converting any color type to RGBA
+    pub(crate) fn from_png(png: &[u8]) -> Result<ImageData> {
+        let mut decoder = Decoder::new(png);
+        decoder.set_transformations(Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|e| Error::Image(Box::new(e)))?;
+
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buf)
+            .map_err(|e| Error::Image(Box::new(e)))?;
+        buf.truncate(info.buffer_size());
+
+        let bytes = match info.color_type {
+            ColorType::Rgba => buf,
+            ColorType::Rgb => buf
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 0xff])
+                .collect(),
+            ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            ColorType::Grayscale => buf.iter().flat_map(|&p| [p, p, p, 0xff]).collect(),
+            // Palettes are expanded by the normalizing transformation
+            ColorType::Indexed => unreachable!(),
+        };
+
+        Ok(ImageData {
+            width: info.width as usize,
+            height: info.height as usize,
+            bytes,
+        })
+    }
+
+    /// Encodes the image as PNG
+    ///
+    /// Fails with `Error::Image` if the size doesn't fit in a PNG or
+    /// doesn't match the number of bytes.
+    pub(crate) fn to_png(&self) -> Result<Vec<u8>> {
+        let width = u32::try_from(self.width).map_err(|e| Error::Image(Box::new(e)))?;
+        let height = u32::try_from(self.height).map_err(|e| Error::Image(Box::new(e)))?;
+
+        let len = self
+            .width
+            .checked_mul(self.height)
+            .and_then(|n| n.checked_mul(4));
+        if len != Some(self.bytes.len()) {
+            return Err(Error::Image(
+                format!(
+                    "{} bytes don't hold a {}x{} RGBA image",
+                    self.bytes.len(),
+                    self.width,
+                    self.height
+                )
+                .into(),
+            ));
+        }
+
+        let mut png = Vec::new();
+
+        let mut encoder = Encoder::new(&mut png, width, height);
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(BitDepth::Eight);
+
+        let mut writer = encoder
+            .write_header()
+            .map_err(|e| Error::Image(Box::new(e)))?;
+        writer
+            .write_image_data(&self.bytes)
+            .map_err(|e| Error::Image(Box::new(e)))?;
+        writer.finish().map_err(|e| Error::Image(Box::new(e)))?;
+
+        Ok(png)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_round_trip() {
+        let image = ImageData {
+            width: 2,
+            height: 1,
+            bytes: vec![0xff, 0, 0, 0xff, 0, 0, 0xff, 0x80],
+        };
+
+        let png = image.to_png().unwrap();
+        assert_eq!(ImageData::from_png(&png).unwrap(), image);
+    }
+
+    #[test]
+    fn rejects_mismatched_size() {
+        let image = ImageData {
+            width: 2,
+            height: 2,
+            bytes: vec![0; 4],
+        };
+
+        assert!(matches!(image.to_png(), Err(Error::Image(_))));
+    }
+
+    #[test]
+    fn rejects_oversized_image() {
+        let image = ImageData {
+            width: usize::MAX,
+            height: 0,
+            bytes: Vec::new(),
+        };
+
+        assert!(matches!(image.to_png(), Err(Error::Image(_))));
+    }
+}
@@ -24,17 +24,20 @@ limitations under the License.
 //! environments, macOS and windows.
 //!
 //! Also adds convenience functions for [get_contents](fn.get_contents.html) and
-//! [set_contents](fn.set_contents.html).
+//! [set_contents](fn.set_contents.html), which share one lazily created
+//! context.
 //!
 //! On Linux it will first attempt to setup a Wayland clipboard provider.  If that
-//! fails it will then fallback to the X11 clipboard provider.
+//! fails it will then fallback to the X11 clipboard provider, then to an installed
+//! clipboard program like xclip, and without a display server to tmux or GNU
+//! screen when running inside them.
 //!
 //! ## Examples
 //!
 //! Using ClipboardContext to create a clipboard provider:
 //!
 //! ```
-//! use cli_clipboard::{ClipboardContext, ClipboardProvider};
+//! use cli_clipboard::{ClipboardContext, ClipboardOps, ClipboardProvider};
 //!
 //! let mut ctx = ClipboardContext::new().unwrap();
 //! let the_string = "Hello, world!";
@@ -71,10 +74,27 @@ extern crate x11_clipboard as x11_clipboard_crate;
 #[macro_use]
 extern crate objc;
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
 mod common;
-pub use common::ClipboardProvider;
+mod error;
+pub mod fallback_clipboard;
+mod files;
+mod html;
+mod image;
+pub mod mime;
+#[cfg(any(test, feature = "testing"))]
+pub mod mock_clipboard;
+mod shared;
+pub use common::{ClipboardOps, ClipboardProvider};
+pub use error::{BoxedError, Error, Result};
+pub use files::Operation;
+pub use image::ImageData;
+pub use shared::{reset_context, shutdown_context};
+
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+))]
+mod deadline;
 
 #[cfg(all(
     unix,
@@ -88,12 +108,67 @@ pub mod wayland_clipboard;
 ))]
 pub mod x11_clipboard;
 
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+))]
+mod x11_server;
+
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+))]
+pub mod command_clipboard;
+
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+))]
+pub mod file_clipboard;
+
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+))]
+pub mod osc52_clipboard;
+
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+))]
+mod process;
+
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+))]
+pub mod screen_clipboard;
+
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+))]
+pub mod tmux_clipboard;
+
 #[cfg(all(
     unix,
     not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
 ))]
 pub mod linux_clipboard;
 
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+))]
+pub mod watcher;
+
+#[cfg(all(
+    feature = "async",
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+))]
+pub mod async_clipboard;
+
 #[cfg(windows)]
 pub mod windows_clipboard;
 
@@ -111,6 +186,14 @@ pub mod android_clipboard;
 ))]
 pub type ClipboardContext = linux_clipboard::LinuxClipboardContext;
 
+/// Auto-detecting context for the primary selection, which holds the
+/// most recently selected text
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+))]
+pub type PrimaryContext = linux_clipboard::LinuxClipboardContext<x11_clipboard::Primary>;
+
 #[cfg(windows)]
 pub type ClipboardContext = windows_clipboard::WindowsClipboardContext;
 
@@ -122,14 +205,16 @@ pub type ClipboardContext = android_clipboard::AndroidClipboardContext;
 
 /// Get the current clipboard contents
 ///
+/// Uses a context shared by the free functions, which is created on the
+/// first call and kept until [`reset_context`] or [`shutdown_context`].
+///
 /// # Example
 /// ```
 /// cli_clipboard::set_contents("testing".to_owned()).unwrap();
 /// assert_eq!(cli_clipboard::get_contents().unwrap(), "testing");
 /// ```
 pub fn get_contents() -> Result<String> {
-    let mut ctx = ClipboardContext::new()?;
-    ctx.get_contents()
+    shared::with_context(|ctx| ctx.get_contents())
 }
 
 /// Write a string to the clipboard
@@ -137,7 +222,10 @@ pub fn get_contents() -> Result<String> {
 /// This uses the platform default behavior for setting clipboard contents.
 /// Other users of the Wayland or X11 clipboard will only see the contents
 /// copied to the clipboard so long as the process copying to the
-/// clipboard exists.
+/// clipboard exists, and the shared context (see [`get_contents`]) hasn't
+/// been reset. On X11,
+/// [`X11ClipboardContext::set_persistent`](x11_clipboard::X11ClipboardContext::set_persistent)
+/// keeps the contents available after exit.
 /// MacOS and Windows clipboard contents will stick around after your
 /// application exits.
 ///
@@ -147,9 +235,7 @@ pub fn get_contents() -> Result<String> {
 /// assert_eq!(cli_clipboard::get_contents().unwrap(), "testing");
 /// ```
 pub fn set_contents(data: String) -> Result<()> {
-    let mut ctx = ClipboardContext::new()?;
-    ctx.set_contents(data)?;
-    Ok(())
+    shared::with_context(|ctx| ctx.set_contents(data))
 }
 
 #[cfg(test)]
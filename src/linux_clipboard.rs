@@ -1,50 +1,436 @@
+use crate::command_clipboard::CommandClipboardContext;
 use crate::common::*;
+use crate::deadline::TIMEOUT;
+use crate::fallback_clipboard::{FallbackBuilder, FallbackClipboard};
+use crate::file_clipboard::FileClipboardContext;
+use crate::screen_clipboard::ScreenClipboardContext;
+use crate::tmux_clipboard::TmuxClipboardContext;
 use crate::wayland_clipboard::WaylandClipboardContext;
-use crate::x11_clipboard::{Clipboard, X11ClipboardContext};
-use crate::Result;
+use crate::x11_clipboard::{Clipboard, Selection, X11ClipboardContext};
+use crate::{Error, Result};
+use std::env;
+use std::marker::PhantomData;
+use std::time::Duration;
 
-enum LinuxContext {
-    Wayland(WaylandClipboardContext),
-    X11(X11ClipboardContext),
+/// Environment variable that overrides automatic backend detection
+const BACKEND_VAR: &str = "CLI_CLIPBOARD_BACKEND";
+
+/// Clipboard backend used by a [`LinuxClipboardContext`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Backend {
+    /// Use the backend named by the `CLI_CLIPBOARD_BACKEND` environment
+    /// variable (`wayland`, `x11`, `command`, `tmux`, `screen` or
+    /// `file`) if set, otherwise Wayland if the compositor supports the
+    /// data-control protocol, X11 if not, and a clipboard program if
+    /// neither can be connected to. Without a display server it uses
+    /// tmux if `$TMUX` is set and GNU screen if `$STY` is, and a file if
+    /// [`headless_fallback`](ClipboardBuilder::headless_fallback) is set.
+    /// If none works it fails with `Error::NoDisplay` carrying a
+    /// [`FallbackError`](crate::fallback_clipboard::FallbackError) that
+    /// lists why each backend failed
+    Auto,
+    /// Wayland through the data-control protocol
+    Wayland,
+    /// X11, which also works through XWayland
+    X11,
+    /// An installed clipboard program like xclip, see
+    /// [`CommandClipboardContext`]
+    Command,
+    /// A file shared by the user's processes, see
+    /// [`FileClipboardContext`]
+    File,
+    /// The paste buffers of tmux, see [`TmuxClipboardContext`]. Only
+    /// supports the regular clipboard
+    Tmux,
+    /// The paste buffer of GNU screen, see [`ScreenClipboardContext`].
+    /// Only supports the regular clipboard
+    Screen,
+}
+
+impl Backend {
+    fn from_env() -> Backend {
+        Backend::from_value(env::var(BACKEND_VAR).ok().as_deref())
+    }
+
+    /// Interprets the value of `CLI_CLIPBOARD_BACKEND`, ignoring unknown
+    /// names.
+    fn from_value(value: Option<&str>) -> Backend {
+        value.and_then(Backend::parse).unwrap_or(Backend::Auto)
+    }
+
+    fn parse(name: &str) -> Option<Backend> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "auto" | "" => Some(Backend::Auto),
+            "wayland" => Some(Backend::Wayland),
+            "x11" => Some(Backend::X11),
+            "command" => Some(Backend::Command),
+            "file" => Some(Backend::File),
+            "tmux" => Some(Backend::Tmux),
+            "screen" => Some(Backend::Screen),
+            _ => None,
+        }
+    }
 }
 
-pub struct LinuxClipboardContext {
-    context: LinuxContext,
+/// Boxed backend context
+type Context = Box<dyn ClipboardOps + Send>;
+
+/// Clipboard on Linux that picks a backend at runtime: by default
+/// Wayland if available, then X11, clipboard programs, and tmux or GNU
+/// screen, as described for [`Backend::Auto`].
+///
+/// The selection to access is chosen by the type parameter, like for
+/// the backend contexts: the regular clipboard by default, or the
+/// primary selection with [`Primary`](crate::x11_clipboard::Primary).
+pub struct LinuxClipboardContext<S = Clipboard>
+where
+    S: Selection,
+{
+    backend: Backend,
+    context: Context,
+    selection: PhantomData<S>,
 }
 
-impl ClipboardProvider for LinuxClipboardContext {
-    fn new() -> Result<LinuxClipboardContext> {
-        match WaylandClipboardContext::new() {
-            Ok(context) => Ok(LinuxClipboardContext {
-                context: LinuxContext::Wayland(context),
-            }),
-            Err(_) => match X11ClipboardContext::<Clipboard>::new() {
-                Ok(context) => Ok(LinuxClipboardContext {
-                    context: LinuxContext::X11(context),
-                }),
-                Err(err) => Err(err),
-            },
+impl<S> LinuxClipboardContext<S>
+where
+    S: Selection,
+{
+    /// Returns a builder for a context with non-default settings.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cli_clipboard::x11_clipboard::Primary;
+    /// use cli_clipboard::{ClipboardContext, ClipboardOps};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let mut ctx = ClipboardContext::builder()
+    ///     .selection::<Primary>()
+    ///     .timeout(Duration::from_millis(500))
+    ///     .build()
+    ///     .unwrap();
+    /// println!("{}", ctx.get_contents().unwrap());
+    /// ```
+    pub fn builder() -> ClipboardBuilder<S> {
+        ClipboardBuilder::new()
+    }
+
+    /// Creates a context that uses the given backend. A forced backend
+    /// returns its error instead of falling back to the other one.
+    pub fn with_backend(backend: Backend) -> Result<LinuxClipboardContext<S>> {
+        LinuxClipboardContext::builder().backend(backend).build()
+    }
+
+    /// Returns the backend in use, which is never `Backend::Auto`.
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+}
+
+impl<S> ClipboardProvider for LinuxClipboardContext<S>
+where
+    S: Selection,
+{
+    /// Picks the backend as described for [`Backend::Auto`].
+    fn new() -> Result<LinuxClipboardContext<S>> {
+        LinuxClipboardContext::with_backend(Backend::Auto)
+    }
+}
+
+impl<S> ClipboardOps for LinuxClipboardContext<S>
+where
+    S: Selection,
+{
+    forward_clipboard!(|this| this.context);
+}
+
+/// Configures a [`LinuxClipboardContext`], created by
+/// [`LinuxClipboardContext::builder`].
+///
+/// Settings that do not apply to the backend in use are ignored.
+pub struct ClipboardBuilder<S = Clipboard>
+where
+    S: Selection,
+{
+    backend: Backend,
+    timeout: Duration,
+    seat: Option<String>,
+    display: Option<String>,
+    serve_limit: Option<usize>,
+    persistent: bool,
+    persist_on_exit: bool,
+    headless_fallback: bool,
+    selection: PhantomData<S>,
+}
+
+// Derived, it would require the selection to be Clone
+impl<S> Clone for ClipboardBuilder<S>
+where
+    S: Selection,
+{
+    fn clone(&self) -> Self {
+        ClipboardBuilder {
+            backend: self.backend,
+            timeout: self.timeout,
+            seat: self.seat.clone(),
+            display: self.display.clone(),
+            serve_limit: self.serve_limit,
+            persistent: self.persistent,
+            persist_on_exit: self.persist_on_exit,
+            headless_fallback: self.headless_fallback,
+            selection: PhantomData,
         }
     }
+}
 
-    fn get_contents(&mut self) -> Result<String> {
-        match &mut self.context {
-            LinuxContext::Wayland(context) => context.get_contents(),
-            LinuxContext::X11(context) => context.get_contents(),
+impl<S> ClipboardBuilder<S>
+where
+    S: Selection,
+{
+    fn new() -> ClipboardBuilder<S> {
+        ClipboardBuilder {
+            backend: Backend::Auto,
+            timeout: TIMEOUT,
+            seat: None,
+            display: None,
+            serve_limit: None,
+            persistent: false,
+            persist_on_exit: false,
+            headless_fallback: false,
+            selection: PhantomData,
         }
     }
 
-    fn set_contents(&mut self, content: String) -> Result<()> {
-        match &mut self.context {
-            LinuxContext::Wayland(context) => context.set_contents(content),
-            LinuxContext::X11(context) => context.set_contents(content),
+    /// Sets the backend to use, see [`Backend`]. Defaults to
+    /// `Backend::Auto`.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Sets the selection to access, `Clipboard` or `Primary`.
+    ///
+    /// [`persist_on_exit`](ClipboardBuilder::persist_on_exit) only applies
+    /// to the clipboard, and is turned off when switching to `Primary`.
+    pub fn selection<T: Selection>(self) -> ClipboardBuilder<T> {
+        ClipboardBuilder {
+            backend: self.backend,
+            timeout: self.timeout,
+            seat: self.seat,
+            display: self.display,
+            serve_limit: self.serve_limit,
+            persistent: self.persistent,
+            // The clipboard manager only saves the clipboard
+            persist_on_exit: self.persist_on_exit && !T::is_primary(),
+            headless_fallback: self.headless_fallback,
+            selection: PhantomData,
         }
     }
 
-    fn clear(&mut self) -> Result<()> {
-        match &mut self.context {
-            LinuxContext::Wayland(context) => context.clear(),
-            LinuxContext::X11(context) => context.clear(),
+    /// Sets how long get, set and clear may take before failing with
+    /// `Error::Timeout`. Defaults to 3 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Restricts Wayland access to the named seat.
+    pub fn seat(mut self, seat: impl Into<String>) -> Self {
+        self.seat = Some(seat.into());
+        self
+    }
+
+    /// Sets the X display to connect to instead of `$DISPLAY`.
+    pub fn display(mut self, display: impl Into<String>) -> Self {
+        self.display = Some(display.into());
+        self
+    }
+
+    /// Stops serving copied contents on Wayland after the given number
+    /// of paste requests.
+    pub fn serve_limit(mut self, requests: usize) -> Self {
+        self.serve_limit = Some(requests);
+        self
+    }
+
+    /// Keeps copied contents available on X11 after the process exits,
+    /// see [`X11ClipboardContext::set_persistent`].
+    pub fn persistent(mut self, persistent: bool) -> Self {
+        self.persistent = persistent;
+        self
+    }
+
+    /// Lets `Backend::Auto` fall back to a file, see
+    /// [`FileClipboardContext`], when no display server can be reached
+    /// and there is no tmux or GNU screen to use instead. Off by default,
+    /// so that a missing display is reported as `Error::NoDisplay`.
+    pub fn headless_fallback(mut self, fallback: bool) -> Self {
+        self.headless_fallback = fallback;
+        self
+    }
+
+    /// Creates the context.
+    pub fn build(self) -> Result<LinuxClipboardContext<S>> {
+        let backend = match self.backend {
+            Backend::Auto => Backend::from_env(),
+            backend => backend,
+        };
+
+        let (backend, context): (Backend, Context) = match backend {
+            Backend::Wayland => (backend, Box::new(self.wayland()?)),
+            Backend::X11 => (backend, Box::new(self.x11()?)),
+            Backend::Command => (backend, Box::new(self.command()?)),
+            Backend::File => (backend, Box::new(self.file()?)),
+            Backend::Tmux => (backend, Box::new(self.tmux()?)),
+            Backend::Screen => (backend, Box::new(self.screen()?)),
+            Backend::Auto => {
+                let (name, context) = self.auto_chain().build()?.into_provider();
+                let backend = Backend::parse(&name).expect("providers are named after backends");
+                (backend, context)
+            }
+        };
+
+        Ok(LinuxClipboardContext {
+            backend,
+            context,
+            selection: PhantomData,
+        })
+    }
+
+    fn wayland(&self) -> Result<WaylandClipboardContext<S>> {
+        let mut context = WaylandClipboardContext::new()?;
+        context.set_timeout(self.timeout);
+        context.set_seat(self.seat.clone());
+        context.set_serve_limit(self.serve_limit);
+        Ok(context)
+    }
+
+    fn x11(&self) -> Result<X11ClipboardContext<S>> {
+        let mut context = X11ClipboardContext::with_display(self.display.as_deref())?;
+        context.set_timeout(self.timeout);
+        context.set_persistent(self.persistent);
+        context.set_persist_on_exit(self.persist_on_exit);
+        Ok(context)
+    }
+
+    fn command(&self) -> Result<CommandClipboardContext<S>> {
+        let mut context = CommandClipboardContext::new()?;
+        context.set_timeout(self.timeout);
+        Ok(context)
+    }
+
+    fn file(&self) -> Result<FileClipboardContext<S>> {
+        let mut context = FileClipboardContext::new()?;
+        context.set_timeout(self.timeout);
+        Ok(context)
+    }
+
+    fn tmux(&self) -> Result<TmuxClipboardContext> {
+        if S::is_primary() {
+            return Err(Error::Unsupported);
+        }
+        let mut context = TmuxClipboardContext::new()?;
+        context.set_timeout(self.timeout);
+        Ok(context)
+    }
+
+    fn screen(&self) -> Result<ScreenClipboardContext> {
+        if S::is_primary() {
+            return Err(Error::Unsupported);
+        }
+        let mut context = ScreenClipboardContext::new()?;
+        context.set_timeout(self.timeout);
+        Ok(context)
+    }
+
+    /// Lists the backends `Backend::Auto` tries, in order, named like
+    /// in `CLI_CLIPBOARD_BACKEND`.
+    pub(crate) fn auto_chain(&self) -> FallbackBuilder {
+        let mut chain = FallbackClipboard::builder()
+            .provider_with("wayland", self.factory(Self::wayland))
+            .provider_with("x11", self.factory(Self::x11))
+            .provider_with("command", self.factory(Self::command));
+        if !S::is_primary() {
+            if env::var_os("TMUX").is_some() {
+                chain = chain.provider_with("tmux", self.factory(Self::tmux));
+            }
+            if env::var_os("STY").is_some() {
+                chain = chain.provider_with("screen", self.factory(Self::screen));
+            }
+        }
+        if self.headless_fallback {
+            chain = chain.provider_with("file", self.factory(Self::file));
         }
+        chain
+    }
+
+    /// Returns a function creating a context with `create` and a copy of
+    /// the settings.
+    fn factory<C>(&self, create: fn(&Self) -> Result<C>) -> impl FnOnce() -> Result<C> {
+        let builder = self.clone();
+        move || create(&builder)
+    }
+}
+
+impl ClipboardBuilder<Clipboard> {
+    /// Hands the clipboard over to the X11 clipboard manager when the
+    /// context is dropped, see [`X11ClipboardContext::persist_on_exit`].
+    pub fn persist_on_exit(mut self, persist: bool) -> Self {
+        self.persist_on_exit = persist;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::x11_clipboard::Primary;
+
+    #[test]
+    fn parses_backend_names() {
+        assert_eq!(Backend::parse("X11"), Some(Backend::X11));
+        assert_eq!(Backend::parse(" wayland\n"), Some(Backend::Wayland));
+        assert_eq!(Backend::parse("File"), Some(Backend::File));
+        assert_eq!(Backend::parse("screen"), Some(Backend::Screen));
+        assert_eq!(Backend::parse("command"), Some(Backend::Command));
+        assert_eq!(Backend::parse(""), Some(Backend::Auto));
+        assert_eq!(Backend::parse("tmux"), Some(Backend::Tmux));
+        assert_eq!(Backend::parse("xwayland"), None);
+    }
+
+    #[test]
+    fn reads_backend_variable() {
+        assert_eq!(Backend::from_value(Some("x11")), Backend::X11);
+        assert_eq!(Backend::from_value(Some("xwayland")), Backend::Auto);
+        assert_eq!(Backend::from_value(None), Backend::Auto);
+    }
+
+    #[test]
+    fn builder_carries_settings_over() {
+        let builder = LinuxClipboardContext::<Clipboard>::builder()
+            .backend(Backend::X11)
+            .timeout(Duration::from_millis(100))
+            .seat("seat1")
+            .display(":1")
+            .serve_limit(2)
+            .persistent(true)
+            .persist_on_exit(true)
+            .headless_fallback(true)
+            .selection::<Primary>();
+
+        assert_eq!(builder.backend, Backend::X11);
+        assert_eq!(builder.timeout, Duration::from_millis(100));
+        assert_eq!(builder.seat.as_deref(), Some("seat1"));
+        assert_eq!(builder.display.as_deref(), Some(":1"));
+        assert_eq!(builder.serve_limit, Some(2));
+        assert!(builder.persistent);
+        assert!(!builder.persist_on_exit);
+        assert!(builder.headless_fallback);
+
+        let builder = LinuxClipboardContext::<Clipboard>::builder()
+            .persist_on_exit(true)
+            .selection::<Clipboard>();
+        assert!(builder.persist_on_exit);
     }
 }
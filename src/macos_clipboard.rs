@@ -15,7 +15,7 @@ limitations under the License.
 */
 
 use crate::common::*;
-use crate::Result;
+use crate::{Error, Result};
 use objc::runtime::{Class, Object};
 use objc_foundation::{INSArray, INSObject, INSString};
 use objc_foundation::{NSArray, NSDictionary, NSObject, NSString};
@@ -32,15 +32,20 @@ extern "C" {}
 
 impl ClipboardProvider for MacOSClipboardContext {
     fn new() -> Result<MacOSClipboardContext> {
-        let cls = Class::get("NSPasteboard").ok_or_else(|| "Class::get(\"NSPasteboard\")")?;
+        let cls = Class::get("NSPasteboard")
+            .ok_or_else(|| Error::Backend("Class::get(\"NSPasteboard\")".into()))?;
         let pasteboard: *mut Object = unsafe { msg_send![cls, generalPasteboard] };
         if pasteboard.is_null() {
-            return Err("NSPasteboard#generalPasteboard returned null".into());
+            return Err(Error::Backend(
+                "NSPasteboard#generalPasteboard returned null".into(),
+            ));
         }
         let pasteboard: Id<Object> = unsafe { Id::from_ptr(pasteboard) };
         Ok(MacOSClipboardContext { pasteboard })
     }
+}
 
+impl ClipboardOps for MacOSClipboardContext {
     fn get_contents(&mut self) -> Result<String> {
         let string_class: Id<NSObject> = {
             let cls: Id<Class> = unsafe { Id::from_ptr(class("NSString")) };
@@ -52,12 +57,14 @@ impl ClipboardProvider for MacOSClipboardContext {
             let obj: *mut NSArray<NSString> =
                 msg_send![self.pasteboard, readObjectsForClasses:&*classes options:&*options];
             if obj.is_null() {
-                return Err("pasteboard#readObjectsForClasses:options: returned null".into());
+                return Err(Error::Backend(
+                    "pasteboard#readObjectsForClasses:options: returned null".into(),
+                ));
             }
             Id::from_ptr(obj)
         };
         if string_array.count() == 0 {
-            Err("pasteboard#readObjectsForClasses:options: returned empty".into())
+            Err(Error::ClipboardEmpty)
         } else {
             Ok(string_array[0].as_str().to_owned())
         }
@@ -70,7 +77,9 @@ impl ClipboardProvider for MacOSClipboardContext {
         if success {
             Ok(())
         } else {
-            Err("NSPasteboard#writeObjects: returned false".into())
+            Err(Error::Backend(
+                "NSPasteboard#writeObjects: returned false".into(),
+            ))
         }
     }
 
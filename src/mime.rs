@@ -0,0 +1,52 @@
+//! MIME type names used by the byte-oriented clipboard methods.
+
+/// Plain UTF-8 text, as returned by
+/// [`get_contents`](crate::ClipboardOps::get_contents)
+pub const TEXT: &str = "text/plain;charset=utf-8";
+
+/// HTML documents or fragments, used by
+/// [`get_html`](crate::ClipboardOps::get_html) and
+/// [`set_html`](crate::ClipboardOps::set_html)
+pub const HTML: &str = "text/html";
+
+/// PNG images, used by [`get_image`](crate::ClipboardOps::get_image)
+/// and [`set_image`](crate::ClipboardOps::set_image)
+pub const PNG: &str = "image/png";
+
+/// Lists of file URIs, used by
+/// [`get_files`](crate::ClipboardOps::get_files) and
+/// [`set_files`](crate::ClipboardOps::set_files)
+pub const URI_LIST: &str = "text/uri-list";
+
+/// File URIs preceded by the [`Operation`](crate::Operation), as used by
+/// GNOME file managers
+pub const GNOME_COPIED_FILES: &str = "x-special/gnome-copied-files";
+
+/// Marks files on the clipboard as cut (`1`) or copied (`0`), as used by
+/// KDE file managers
+pub const KDE_CUT_SELECTION: &str = "application/x-kde-cutselection";
+
+/// Whether `mime` names a plain text format.
+///
+/// Besides `text/plain` with any parameters this accepts the X11 target
+/// names conventionally used for text.
+pub(crate) fn is_text(mime: &str) -> bool {
+    match mime {
+        "text/plain" | "UTF8_STRING" | "STRING" | "TEXT" => true,
+        x => x.starts_with("text/plain;"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_mime_types() {
+        assert!(is_text(TEXT));
+        assert!(is_text("text/plain"));
+        assert!(is_text("UTF8_STRING"));
+        assert!(!is_text("text/html"));
+        assert!(!is_text("image/png"));
+    }
+}
@@ -0,0 +1,252 @@
+//! In-memory clipboard for testing code that uses this crate, enabled by
+//! the `testing` feature.
+//!
+//! # Example
+//!
+//! ```
+//! use cli_clipboard::mock_clipboard::{MockClipboardContext, MockSelection};
+//! use cli_clipboard::{ClipboardOps, ClipboardProvider, Error};
+//!
+//! let mut ctx = MockClipboardContext::new().unwrap();
+//! ctx.set_html("<b>hi</b>".to_owned(), None).unwrap();
+//! assert_eq!(ctx.get_contents().unwrap(), "hi");
+//!
+//! // Contexts for other selections share the store
+//! let mut primary = ctx.for_selection(MockSelection::Primary);
+//! assert_eq!(primary.get_contents().unwrap(), "");
+//!
+//! ctx.fail_next(Error::Timeout);
+//! assert!(matches!(ctx.get_contents(), Err(Error::Timeout)));
+//! ```
+
+use crate::common::{ClipboardOps, ClipboardProvider};
+use crate::{mime, Error, Result};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Selection accessed by a [`MockClipboardContext`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MockSelection {
+    /// The regular clipboard
+    Clipboard,
+    /// The primary selection
+    Primary,
+}
+
+type Failure = Box<dyn Fn() -> Error + Send>;
+
+#[derive(Default)]
+struct Store {
+    selections: HashMap<MockSelection, Vec<(String, Vec<u8>)>>,
+    next_failures: VecDeque<Error>,
+    failure: Option<Failure>,
+}
+
+/// Clipboard provider that keeps its contents in memory.
+///
+/// Every selection holds a list of formats, like the Linux backends:
+/// plain text, HTML, images and file lists behave as on Wayland and
+/// X11. Failures can be injected to exercise error handling.
+///
+/// Each context created with `new` has its own store. Clones, and the
+/// contexts returned by [`for_selection`](Self::for_selection), share
+/// the store of the context they were made from.
+#[derive(Clone)]
+pub struct MockClipboardContext {
+    store: Arc<Mutex<Store>>,
+    selection: MockSelection,
+}
+
+impl MockClipboardContext {
+    /// Returns a context sharing this context's store that accesses
+    /// `selection`.
+    pub fn for_selection(&self, selection: MockSelection) -> MockClipboardContext {
+        MockClipboardContext {
+            store: Arc::clone(&self.store),
+            selection,
+        }
+    }
+
+    /// Returns the selection this context accesses.
+    pub fn selection(&self) -> MockSelection {
+        self.selection
+    }
+
+    /// Makes the next operation on the store fail with `error`. Several
+    /// queued errors are returned by consecutive operations.
+    pub fn fail_next(&self, error: Error) {
+        self.lock().next_failures.push_back(error);
+    }
+
+    /// Makes every operation on the store fail with the error returned
+    /// by `failure`, until [`clear_failures`](Self::clear_failures) is
+    /// called.
+    pub fn fail_with<F>(&self, failure: F)
+    where
+        F: Fn() -> Error + Send + 'static,
+    {
+        self.lock().failure = Some(Box::new(failure));
+    }
+
+    /// Removes all injected failures.
+    pub fn clear_failures(&self) {
+        let mut store = self.lock();
+        store.next_failures.clear();
+        store.failure = None;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Store> {
+        // A panicking test must not poison the store for other tests
+        self.store.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Locks the store for an operation, returning an injected failure
+    /// instead if there is one.
+    fn begin(&self) -> Result<MutexGuard<'_, Store>> {
+        let mut store = self.lock();
+        if let Some(error) = store.next_failures.pop_front() {
+            return Err(error);
+        }
+        if let Some(failure) = &store.failure {
+            return Err(failure());
+        }
+        Ok(store)
+    }
+}
+
+impl ClipboardProvider for MockClipboardContext {
+    /// Creates a context for the regular clipboard with an empty store.
+    fn new() -> Result<MockClipboardContext> {
+        Ok(MockClipboardContext {
+            store: Arc::default(),
+            selection: MockSelection::Clipboard,
+        })
+    }
+}
+
+impl ClipboardOps for MockClipboardContext {
+    /// Returns the first plain text format, or an empty string if the
+    /// selection is empty.
+    fn get_contents(&mut self) -> Result<String> {
+        match self.get_bytes(mime::TEXT) {
+            Ok(contents) => Ok(String::from_utf8(contents)?),
+            Err(Error::ClipboardEmpty | Error::FormatUnavailable) => Ok("".to_string()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn set_contents(&mut self, data: String) -> Result<()> {
+        self.set_formats(vec![(mime::TEXT.to_string(), data.into_bytes())])
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.begin()?.selections.remove(&self.selection);
+        Ok(())
+    }
+
+    /// Plain text MIME types match any plain text format.
+    fn get_bytes(&mut self, mime: &str) -> Result<Vec<u8>> {
+        let store = self.begin()?;
+        let formats = store
+            .selections
+            .get(&self.selection)
+            .ok_or(Error::ClipboardEmpty)?;
+
+        formats
+            .iter()
+            .find(|(format, _)| format == mime || (mime::is_text(mime) && mime::is_text(format)))
+            .map(|(_, data)| data.clone())
+            .ok_or(Error::FormatUnavailable)
+    }
+
+    fn set_bytes(&mut self, mime: &str, data: Vec<u8>) -> Result<()> {
+        self.set_formats(vec![(mime.to_owned(), data)])
+    }
+
+    fn available_formats(&mut self) -> Result<Vec<String>> {
+        let store = self.begin()?;
+        let mut formats: Vec<String> = match store.selections.get(&self.selection) {
+            Some(formats) => formats.iter().map(|(format, _)| format.clone()).collect(),
+            None => Vec::new(),
+        };
+
+        formats.sort();
+        formats.dedup();
+        Ok(formats)
+    }
+
+    fn set_formats(&mut self, formats: Vec<(String, Vec<u8>)>) -> Result<()> {
+        self.begin()?.selections.insert(self.selection, formats);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ImageData, Operation};
+    use std::path::PathBuf;
+
+    #[test]
+    fn selections_share_the_store() {
+        let mut clipboard = MockClipboardContext::new().unwrap();
+        let mut primary = clipboard.for_selection(MockSelection::Primary);
+
+        clipboard.set_contents("regular".to_owned()).unwrap();
+        primary.set_contents("primary".to_owned()).unwrap();
+        assert_eq!(clipboard.clone().get_contents().unwrap(), "regular");
+        assert_eq!(primary.get_contents().unwrap(), "primary");
+
+        primary.clear().unwrap();
+        assert_eq!(primary.get_contents().unwrap(), "");
+        assert!(matches!(
+            primary.get_bytes(mime::TEXT),
+            Err(Error::ClipboardEmpty)
+        ));
+        assert_eq!(
+            MockClipboardContext::new().unwrap().get_contents().unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn rich_formats() {
+        let mut ctx = MockClipboardContext::new().unwrap();
+
+        let image = ImageData {
+            width: 1,
+            height: 1,
+            bytes: vec![1, 2, 3, 4],
+        };
+        ctx.set_image(image.clone()).unwrap();
+        assert_eq!(ctx.get_image().unwrap(), image);
+        assert_eq!(ctx.available_formats().unwrap(), [mime::PNG]);
+
+        // Absolute paths look different elsewhere
+        if cfg!(unix) {
+            let paths = vec![PathBuf::from("/tmp/a")];
+            ctx.set_files(&paths, Operation::Cut).unwrap();
+            assert_eq!(ctx.get_files().unwrap(), (paths, Operation::Cut));
+            assert_eq!(ctx.get_contents().unwrap(), "/tmp/a");
+        }
+    }
+
+    #[test]
+    fn injected_failures() {
+        let mut ctx = MockClipboardContext::new().unwrap();
+
+        ctx.fail_next(Error::Timeout);
+        assert!(matches!(
+            ctx.set_contents("a".to_owned()),
+            Err(Error::Timeout)
+        ));
+        ctx.set_contents("a".to_owned()).unwrap();
+
+        ctx.fail_with(|| Error::Unsupported);
+        assert!(matches!(ctx.get_contents(), Err(Error::Unsupported)));
+        assert!(matches!(ctx.clear(), Err(Error::Unsupported)));
+
+        ctx.clear_failures();
+        assert_eq!(ctx.get_contents().unwrap(), "a");
+    }
+}
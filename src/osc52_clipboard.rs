@@ -0,0 +1,385 @@
+//! Clipboard of the terminal emulator, through OSC 52 escape sequences.
+
+use crate::common::*;
+use crate::deadline::{self, TIMEOUT};
+use crate::x11_clipboard::{Clipboard, Selection};
+use crate::{Error, Result};
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// The controlling terminal of the process
+const TTY: &str = "/dev/tty";
+/// GNU screen drops device control strings longer than this
+const SCREEN_CHUNK: usize = 768;
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// How escape sequences get through a terminal multiplexer to the
+/// terminal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Passthrough {
+    /// Write the sequences as they are
+    None,
+    /// Wrap the sequences in tmux passthrough sequences. tmux 3.3 and
+    /// later only forwards them with the `allow-passthrough` option set
+    Tmux,
+    /// Wrap the sequences in GNU screen device control strings
+    Screen,
+}
+
+impl Passthrough {
+    /// Detects tmux from `$TMUX` and GNU screen from `$STY`, or from
+    /// `$TERM` starting with `screen` outside tmux.
+    pub fn detect() -> Passthrough {
+        let term = env::var("TERM").unwrap_or_default();
+        if env::var_os("TMUX").is_some() {
+            Passthrough::Tmux
+        } else if env::var_os("STY").is_some() || term.starts_with("screen") {
+            Passthrough::Screen
+        } else {
+            Passthrough::None
+        }
+    }
+
+    fn wrap(self, sequence: &[u8]) -> Vec<u8> {
+        match self {
+            Passthrough::None => sequence.to_vec(),
+            Passthrough::Tmux => {
+                let mut wrapped = b"\x1bPtmux;".to_vec();
+                for &byte in sequence {
+                    // Escape characters inside are doubled
+                    if byte == 0x1b {
+                        wrapped.push(0x1b);
+                    }
+                    wrapped.push(byte);
+                }
+                wrapped.extend_from_slice(b"\x1b\\");
+                wrapped
+            }
+            Passthrough::Screen => {
+                let mut wrapped = Vec::new();
+                for chunk in sequence.chunks(SCREEN_CHUNK) {
+                    wrapped.extend_from_slice(b"\x1bP");
+                    wrapped.extend_from_slice(chunk);
+                    wrapped.extend_from_slice(b"\x1b\\");
+                }
+                wrapped
+            }
+        }
+    }
+}
+
+/// Clipboard of the terminal emulator the process runs in.
+///
+/// Contents are sent to the terminal with OSC 52 escape sequences
+/// written to the controlling terminal, so copying works in SSH
+/// sessions and other places without a display server, as long as the
+/// terminal supports OSC 52. Sequences are wrapped for tmux and GNU
+/// screen as detected by [`Passthrough::detect`].
+///
+/// Reading the clipboard needs the terminal to answer the OSC 52
+/// query, which many terminals refuse for security reasons, so it is
+/// disabled by default and `get_contents` fails with
+/// `Error::Unsupported`. See [`set_query`](Self::set_query).
+///
+/// # Example
+///
+/// ```no_run
+/// use cli_clipboard::osc52_clipboard::Osc52ClipboardContext;
+/// use cli_clipboard::{ClipboardOps, ClipboardProvider};
+///
+/// let mut ctx: Osc52ClipboardContext = Osc52ClipboardContext::new().unwrap();
+/// ctx.set_contents("copied over SSH".to_owned()).unwrap();
+/// ```
+pub struct Osc52ClipboardContext<S = Clipboard>
+where
+    S: Selection,
+{
+    tty: PathBuf,
+    passthrough: Passthrough,
+    query: bool,
+    timeout: Duration,
+    selection: PhantomData<S>,
+}
+
+impl<S> ClipboardProvider for Osc52ClipboardContext<S>
+where
+    S: Selection,
+{
+    /// Fails with `Error::NoDisplay` if the process has no controlling
+    /// terminal.
+    fn new() -> Result<Osc52ClipboardContext<S>> {
+        OpenOptions::new()
+            .write(true)
+            .open(TTY)
+            .map_err(|e| Error::NoDisplay(Box::new(e)))?;
+
+        Ok(Osc52ClipboardContext::with_tty(TTY))
+    }
+}
+
+impl<S> ClipboardOps for Osc52ClipboardContext<S>
+where
+    S: Selection,
+{
+    fn get_contents(&mut self) -> Result<String> {
+        self.get_contents_with_deadline(self.deadline())
+    }
+
+    fn set_contents(&mut self, data: String) -> Result<()> {
+        self.write(&encode(data.as_bytes()))
+    }
+
+    /// Asks the terminal to clear the selection, which not every
+    /// terminal supports.
+    fn clear(&mut self) -> Result<()> {
+        // Data that isn't base64 clears the selection
+        self.write("!")
+    }
+
+    /// Queries the terminal for the selection, failing with
+    /// `Error::Timeout` if it has not answered by `deadline`.
+    fn get_contents_with_deadline(&mut self, deadline: Instant) -> Result<String> {
+        if !self.query {
+            return Err(Error::Unsupported);
+        }
+
+        let mut tty = OpenOptions::new().read(true).write(true).open(&self.tty)?;
+        let _raw = RawMode::enable(tty.as_raw_fd())?;
+        tty.write_all(&self.sequence("?"))?;
+        tty.flush()?;
+
+        let mut response = Vec::new();
+        let mut buffer = [0; 1024];
+        loop {
+            if let Some(data) = parse_response(&response) {
+                let contents =
+                    decode(data).ok_or_else(|| Error::Backend("invalid OSC 52 response".into()))?;
+                return Ok(String::from_utf8(contents)?);
+            }
+
+            let now = Instant::now();
+            if now >= deadline || !deadline::wait_readable(tty.as_raw_fd(), deadline - now)? {
+                return Err(Error::Timeout);
+            }
+            match tty.read(&mut buffer) {
+                Ok(0) => return Err(Error::Backend("the terminal closed".into())),
+                Ok(len) => response.extend_from_slice(&buffer[..len]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl<S> Osc52ClipboardContext<S>
+where
+    S: Selection,
+{
+    /// Writes the escape sequences to the given terminal device instead
+    /// of the controlling terminal.
+    pub fn with_tty(tty: impl Into<PathBuf>) -> Osc52ClipboardContext<S> {
+        Osc52ClipboardContext {
+            tty: tty.into(),
+            passthrough: Passthrough::detect(),
+            query: false,
+            timeout: TIMEOUT,
+            selection: PhantomData,
+        }
+    }
+
+    /// Sets how the escape sequences are wrapped for a terminal
+    /// multiplexer.
+    pub fn set_passthrough(&mut self, passthrough: Passthrough) {
+        self.passthrough = passthrough;
+    }
+
+    /// Returns how the escape sequences are wrapped.
+    pub fn passthrough(&self) -> Passthrough {
+        self.passthrough
+    }
+
+    /// Enables reading the selection by sending the OSC 52 query and
+    /// waiting for the terminal's answer. Terminals that ignore the
+    /// query make reads fail with `Error::Timeout`.
+    pub fn set_query(&mut self, query: bool) {
+        self.query = query;
+    }
+
+    /// Sets how long to wait for the terminal to answer a query.
+    /// Defaults to 3 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn deadline(&self) -> Instant {
+        Instant::now() + self.timeout
+    }
+
+    /// Returns the OSC 52 sequence carrying `data` for the selection,
+    /// wrapped for the multiplexer.
+    fn sequence(&self, data: &str) -> Vec<u8> {
+        let selection = if S::is_primary() { "p" } else { "c" };
+        let sequence = format!("\x1b]52;{};{}\x07", selection, data);
+        self.passthrough.wrap(sequence.as_bytes())
+    }
+
+    fn write(&self, data: &str) -> Result<()> {
+        let mut tty = OpenOptions::new().append(true).open(&self.tty)?;
+        tty.write_all(&self.sequence(data))?;
+        tty.flush()?;
+        Ok(())
+    }
+}
+
+/// Disables line buffering and echo on a terminal until dropped, so
+/// the answer to a query can be read as soon as it arrives.
+struct RawMode {
+    fd: RawFd,
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable(fd: RawFd) -> io::Result<Option<RawMode>> {
+        // Files and pipes standing in for the terminal have no modes
+        if unsafe { libc::isatty(fd) } == 0 {
+            return Ok(None);
+        }
+
+        let mut original = MaybeUninit::uninit();
+        if unsafe { libc::tcgetattr(fd, original.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let original = unsafe { original.assume_init() };
+
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Some(RawMode { fd, original }))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.original) };
+    }
+}
+
+/// Finds the data in an OSC 52 answer terminated by BEL or ST, if it
+/// has been received completely.
+fn parse_response(response: &[u8]) -> Option<&[u8]> {
+    let start = response.windows(5).position(|w| w == b"\x1b]52;")? + 5;
+    let rest = &response[start..];
+    // Skip the selection parameter
+    let rest = &rest[rest.iter().position(|&b| b == b';')? + 1..];
+    let end = rest.iter().position(|&b| b == 0x07 || b == 0x1b)?;
+    if rest[end] == 0x1b && rest.get(end + 1) != Some(&b'\\') {
+        return None;
+    }
+    Some(&rest[..end])
+}
+
+fn encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Decodes base64, tolerating missing padding.
+fn decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(data.len() / 4 * 3);
+    let (mut bits, mut len) = (0u32, 0);
+    for &byte in data.iter().take_while(|&&b| b != b'=') {
+        bits = bits << 6 | BASE64.iter().position(|&b| b == byte)? as u32;
+        len += 6;
+        if len >= 8 {
+            len -= 8;
+            decoded.push((bits >> len) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::x11_clipboard::Primary;
+    use std::fs::{self, File};
+
+    #[test]
+    fn base64() {
+        for (data, encoded) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"\xff\x00bar", "/wBiYXI="),
+        ] {
+            assert_eq!(encode(data), encoded);
+            assert_eq!(decode(encoded.as_bytes()).unwrap(), data);
+        }
+        assert_eq!(decode(b"Zm9vYg").unwrap(), b"foob");
+        assert_eq!(decode(b"Zm9v!"), None);
+    }
+
+    #[test]
+    fn passthrough() {
+        let sequence = b"\x1b]52;c;Zm9v\x07";
+        assert_eq!(Passthrough::None.wrap(sequence), sequence);
+        assert_eq!(
+            Passthrough::Tmux.wrap(sequence),
+            b"\x1bPtmux;\x1b\x1b]52;c;Zm9v\x07\x1b\\"
+        );
+
+        let long = vec![b'a'; SCREEN_CHUNK + 1];
+        let wrapped = Passthrough::Screen.wrap(&long);
+        assert_eq!(wrapped.len(), long.len() + 8);
+        assert!(wrapped.ends_with(b"\x1bPa\x1b\\"));
+    }
+
+    #[test]
+    fn responses() {
+        assert_eq!(parse_response(b"\x1b]52;c;Zm9v\x07"), Some(&b"Zm9v"[..]));
+        assert_eq!(parse_response(b"x\x1b]52;p;Zg==\x1b\\"), Some(&b"Zg=="[..]));
+        assert_eq!(parse_response(b"\x1b]52;c;Zm9v\x1b"), None);
+        assert_eq!(parse_response(b"\x1b]52;c;Zm"), None);
+    }
+
+    #[test]
+    fn writes_sequences() {
+        let path = env::temp_dir().join(format!("cli-clipboard-osc52-{}", std::process::id()));
+        File::create(&path).unwrap();
+
+        let mut ctx: Osc52ClipboardContext<Primary> = Osc52ClipboardContext::with_tty(&path);
+        ctx.set_passthrough(Passthrough::None);
+        ctx.set_contents("foo".to_owned()).unwrap();
+        ctx.clear().unwrap();
+        assert_eq!(
+            fs::read(&path).unwrap(),
+            b"\x1b]52;p;Zm9v\x07\x1b]52;p;!\x07"
+        );
+        assert!(matches!(ctx.get_contents(), Err(Error::Unsupported)));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,143 @@
+//! Running the helper programs some providers drive.
+
+use crate::deadline;
+use crate::{Error, Result};
+use std::io::{self, Write};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Interval between checks whether a program has exited
+const WAIT_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Runs `command` with `input` on its standard input, killing it if it
+/// hasn't finished by `deadline`.
+///
+/// If `capture` is set, standard output is returned and standard error
+/// is included in the error for a failing program. Otherwise both are
+/// discarded, since programs that fork to keep serving the clipboard
+/// would hold the pipes open. A program that can't be found fails with
+/// `Error::NoDisplay`.
+pub(crate) fn run(
+    command: &mut Command,
+    input: Option<Vec<u8>>,
+    capture: bool,
+    deadline: Instant,
+) -> Result<Vec<u8>> {
+    let output = || {
+        if capture {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        }
+    };
+    let mut child = command
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(output())
+        .stderr(output())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::NoDisplay(Box::new(e)),
+            _ => e.into(),
+        })?;
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        // A program that exits without reading everything closes the
+        // pipe, which is not an error here
+        thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+    }
+
+    let result = collect(&mut child, capture, deadline);
+    if result.is_err() {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+    let (stdout, stderr) = result?;
+
+    let status = wait(&mut child, deadline)?;
+    if !status.success() {
+        let program = command.get_program().to_string_lossy();
+        let stderr = String::from_utf8_lossy(&stderr);
+        let message = match stderr.trim() {
+            "" => format!("{} failed with {}", program, status),
+            stderr => format!("{} failed: {}", program, stderr),
+        };
+        return Err(Error::Backend(message.into()));
+    }
+
+    Ok(stdout)
+}
+
+/// Reads standard output and standard error, if captured. Both are read
+/// at once, since a program blocks when the pipe it writes to is full.
+fn collect(child: &mut Child, capture: bool, deadline: Instant) -> Result<(Vec<u8>, Vec<u8>)> {
+    match (capture, &mut child.stdout, &mut child.stderr) {
+        (true, Some(stdout), Some(stderr)) => {
+            let mut outputs = deadline::read_all_to_end(&mut [stdout, stderr], deadline)?;
+            let stderr = outputs.pop().unwrap_or_default();
+            let stdout = outputs.pop().unwrap_or_default();
+            Ok((stdout, stderr))
+        }
+        _ => Ok((Vec::new(), Vec::new())),
+    }
+}
+
+fn wait(child: &mut Child, deadline: Instant) -> Result<std::process::ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Error::Timeout);
+        }
+        thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn runs_programs() {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let output = run(
+            &mut Command::new("cat"),
+            Some(b"piped".to_vec()),
+            true,
+            deadline,
+        );
+        assert_eq!(output.unwrap(), b"piped");
+
+        let mut failing = Command::new("sh");
+        failing.args(["-c", "echo oops >&2; exit 1"]);
+        let error = run(&mut failing, None, true, deadline).unwrap_err();
+        assert_eq!(error.source().unwrap().to_string(), "sh failed: oops");
+
+        let missing = run(
+            &mut Command::new("cli-clipboard-missing"),
+            None,
+            false,
+            deadline,
+        );
+        assert!(matches!(missing, Err(Error::NoDisplay(_))));
+
+        // More error output than a pipe holds, while output is pending
+        let mut chatty = Command::new("sh");
+        chatty.args(["-c", "head -c 200000 /dev/zero >&2; echo done"]);
+        assert_eq!(run(&mut chatty, None, true, deadline).unwrap(), b"done\n");
+
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let slow = run(Command::new("sleep").arg("5"), None, false, deadline);
+        assert!(matches!(slow, Err(Error::Timeout)));
+    }
+}
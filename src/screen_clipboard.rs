@@ -0,0 +1,226 @@
+//! Paste buffer of GNU screen.
+
+use crate::common::*;
+use crate::deadline::TIMEOUT;
+use crate::file_clipboard::runtime_dir;
+use crate::{process, Error, Result};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Numbers the exchange files of this process
+static EXCHANGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Clipboard backed by the paste buffer of a GNU screen session.
+///
+/// Contents are moved through an exchange file with screen's `readbuf`
+/// and `writebuf` commands, sent with `screen -X`. Copied contents can
+/// be pasted with screen's `paste` command.
+///
+/// Every call uses an exchange file of its own in a directory private
+/// to the user. screen carries out commands after `screen -X` returns,
+/// so each command is followed by a `screen -Q` query, which screen
+/// answers once it is done with the commands sent before.
+pub struct ScreenClipboardContext {
+    session: String,
+    timeout: Duration,
+}
+
+impl ClipboardProvider for ScreenClipboardContext {
+    /// Uses the session the process runs in, named by `$STY`. Fails with
+    /// `Error::NoDisplay` outside of screen.
+    fn new() -> Result<ScreenClipboardContext> {
+        match env::var("STY") {
+            Ok(session) if !session.is_empty() => ScreenClipboardContext::with_session(session),
+            _ => Err(Error::NoDisplay("not running inside GNU screen".into())),
+        }
+    }
+}
+
+impl ClipboardOps for ScreenClipboardContext {
+    fn get_contents(&mut self) -> Result<String> {
+        self.get_contents_with_deadline(self.deadline())
+    }
+
+    fn set_contents(&mut self, data: String) -> Result<()> {
+        self.set_contents_with_deadline(data, self.deadline())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.clear_with_deadline(self.deadline())
+    }
+
+    /// Returns the contents of the paste buffer, or an empty string if
+    /// it is empty.
+    fn get_contents_with_deadline(&mut self, deadline: Instant) -> Result<String> {
+        // screen doesn't write the file for an empty paste buffer
+        let exchange = Exchange::new(b"")?;
+        self.run(self.writebuf(&exchange.path), deadline)?;
+        Ok(String::from_utf8(fs::read(&exchange.path)?)?)
+    }
+
+    fn set_contents_with_deadline(&mut self, data: String, deadline: Instant) -> Result<()> {
+        let exchange = Exchange::new(data.as_bytes())?;
+        self.run(self.readbuf(&exchange.path), deadline)
+    }
+
+    /// Empties the paste buffer.
+    fn clear_with_deadline(&mut self, deadline: Instant) -> Result<()> {
+        self.run(self.clear_register(), deadline)
+    }
+}
+
+impl ScreenClipboardContext {
+    /// Uses the named screen session, like `screen -S`.
+    pub fn with_session(session: impl Into<String>) -> Result<ScreenClipboardContext> {
+        Ok(ScreenClipboardContext {
+            session: session.into(),
+            timeout: TIMEOUT,
+        })
+    }
+
+    /// Returns the name of the session in use.
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    /// Sets how long a screen command may take before failing with
+    /// `Error::Timeout`. Defaults to 3 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn deadline(&self) -> Instant {
+        Instant::now() + self.timeout
+    }
+
+    /// Sends `command` and waits until screen has carried it out.
+    fn run(&self, mut command: Command, deadline: Instant) -> Result<()> {
+        process::run(&mut command, None, true, deadline)?;
+        process::run(&mut self.sync(), None, true, deadline)?;
+        Ok(())
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new("screen");
+        command.args(["-S", &self.session, "-X"]);
+        command
+    }
+
+    /// Copies the paste buffer to `file`.
+    fn writebuf(&self, file: &Path) -> Command {
+        let mut command = self.command();
+        command.arg("writebuf").arg(file);
+        command
+    }
+
+    /// Copies `file` to the paste buffer.
+    fn readbuf(&self, file: &Path) -> Command {
+        let mut command = self.command();
+        command.arg("readbuf").arg(file);
+        command
+    }
+
+    fn clear_register(&self) -> Command {
+        // "." is the register holding the paste buffer
+        let mut command = self.command();
+        command.args(["register", ".", ""]);
+        command
+    }
+
+    /// Queries the window number, which screen answers in order with
+    /// the commands it received.
+    fn sync(&self) -> Command {
+        let mut command = Command::new("screen");
+        command.args(["-S", &self.session, "-Q", "number"]);
+        command
+    }
+}
+
+/// Exchange file of a single call, removed when dropped
+struct Exchange {
+    path: PathBuf,
+}
+
+impl Exchange {
+    /// Creates a new exchange file holding `data`, readable only by the
+    /// user.
+    fn new(data: &[u8]) -> Result<Exchange> {
+        let name = format!(
+            "screen-exchange-{}-{}",
+            std::process::id(),
+            EXCHANGES.fetch_add(1, Ordering::Relaxed)
+        );
+        let exchange = Exchange {
+            path: runtime_dir()?.join(name),
+        };
+
+        // A file left over from an earlier process with the same id
+        let _ = fs::remove_file(&exchange.path);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&exchange.path)?
+            .write_all(data)?;
+        Ok(exchange)
+    }
+}
+
+impl Drop for Exchange {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn args(command: &Command) -> Vec<&OsStr> {
+        assert_eq!(command.get_program(), "screen");
+        command.get_args().collect()
+    }
+
+    #[test]
+    fn builds_commands() {
+        let ctx = ScreenClipboardContext::with_session("1234.pts-0.host").unwrap();
+        let file = Path::new("/run/exchange");
+
+        assert_eq!(
+            args(&ctx.writebuf(file)),
+            ["-S", "1234.pts-0.host", "-X", "writebuf", "/run/exchange"]
+        );
+        assert_eq!(
+            args(&ctx.readbuf(file)),
+            ["-S", "1234.pts-0.host", "-X", "readbuf", "/run/exchange"]
+        );
+        assert_eq!(
+            args(&ctx.clear_register()),
+            ["-S", "1234.pts-0.host", "-X", "register", ".", ""]
+        );
+        assert_eq!(args(&ctx.sync()), ["-S", "1234.pts-0.host", "-Q", "number"]);
+    }
+
+    #[test]
+    fn exchange_files_are_private() {
+        let first = Exchange::new(b"contents").unwrap();
+        let second = Exchange::new(b"").unwrap();
+        assert_ne!(first.path, second.path);
+
+        let metadata = fs::metadata(&first.path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read(&first.path).unwrap(), b"contents");
+
+        let path = first.path.clone();
+        drop(first);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,194 @@
+//! Context shared by the free functions like [`get_contents`](crate::get_contents).
+
+use crate::common::ClipboardProvider;
+use crate::Result;
+#[cfg(not(target_os = "macos"))]
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(not(target_os = "macos"))]
+use std::sync::{Mutex, PoisonError};
+
+/// Lazily created context, which is dropped on reset
+#[cfg(not(target_os = "macos"))]
+struct Shared<C> {
+    context: Option<C>,
+    shut_down: bool,
+}
+
+#[cfg(not(target_os = "macos"))]
+impl<C> Shared<C>
+where
+    C: ClipboardProvider,
+{
+    const fn new() -> Shared<C> {
+        Shared {
+            context: None,
+            shut_down: false,
+        }
+    }
+
+    /// Calls `f` with the shared context, creating it first if needed.
+    /// After a shutdown every call gets a context of its own.
+    ///
+    /// The context is dropped when `f` finds the display server gone, so
+    /// that the next call connects again, and when `f` panics, since the
+    /// operation may have left it in any state.
+    fn with_context<T>(&mut self, f: impl FnOnce(&mut C) -> Result<T>) -> Result<T> {
+        if self.shut_down {
+            return f(&mut C::new()?);
+        }
+
+        let context = match self.context.take() {
+            Some(context) => context,
+            None => C::new()?,
+        };
+        let context = self.context.insert(context);
+        let result = match panic::catch_unwind(AssertUnwindSafe(|| f(context))) {
+            Ok(result) => result,
+            Err(panic) => {
+                self.context = None;
+                panic::resume_unwind(panic);
+            }
+        };
+        if matches!(&result, Err(e) if e.is_disconnect()) {
+            self.context = None;
+        }
+        result
+    }
+
+    fn reset(&mut self) {
+        self.context = None;
+    }
+
+    fn shutdown(&mut self) {
+        self.context = None;
+        self.shut_down = true;
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+static SHARED: Mutex<Shared<crate::ClipboardContext>> = Mutex::new(Shared::new());
+
+#[cfg(not(target_os = "macos"))]
+fn shared<T>(f: impl FnOnce(&mut Shared<crate::ClipboardContext>) -> T) -> T {
+    // A panic during an operation drops the context before the lock is
+    // poisoned, so the next call starts over with a new one
+    f(&mut SHARED.lock().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// Calls `f` with the context shared by the free functions. Calls from
+/// other threads wait until `f` returns.
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn with_context<T>(
+    f: impl FnOnce(&mut crate::ClipboardContext) -> Result<T>,
+) -> Result<T> {
+    shared(|shared| shared.with_context(f))
+}
+
+// Contexts on macOS hold an Objective-C object that can't be sent to
+// other threads, and are cheap to create anyway
+#[cfg(target_os = "macos")]
+pub(crate) fn with_context<T>(
+    f: impl FnOnce(&mut crate::ClipboardContext) -> Result<T>,
+) -> Result<T> {
+    f(&mut crate::ClipboardContext::new()?)
+}
+
+/// Drops the context shared by [`get_contents`](crate::get_contents) and
+/// [`set_contents`](crate::set_contents), so that the next call creates
+/// a new one.
+///
+/// The context is created on first use and then kept, so that the free
+/// functions don't connect to the display server on every call. Reset it
+/// to pick up changes to the environment like `$DISPLAY`. A context that
+/// lost its connection to the display server is dropped without a reset.
+///
+/// Contents set through the shared context are no longer served by this
+/// process once it is dropped.
+pub fn reset_context() {
+    #[cfg(not(target_os = "macos"))]
+    shared(Shared::reset);
+}
+
+/// Drops the context shared by [`get_contents`](crate::get_contents) and
+/// [`set_contents`](crate::set_contents) for good, releasing its
+/// connection to the display server.
+///
+/// Call it before the program exits. The shared context lives in a
+/// static, and statics are never dropped, so whatever a context does
+/// when dropped, like handing the clipboard over to the clipboard
+/// manager with `persist_on_exit`, only happens through this function.
+/// It is also useful in long running programs that are done with the
+/// clipboard. The free functions keep working afterwards, but create a
+/// context for every call.
+pub fn shutdown_context() {
+    #[cfg(not(target_os = "macos"))]
+    shared(Shared::shutdown);
+}
+
+#[cfg(all(test, not(target_os = "macos")))]
+mod tests {
+    use super::*;
+    use crate::common::ClipboardOps;
+    use crate::mock_clipboard::MockClipboardContext;
+    use crate::Error;
+
+    #[test]
+    fn reuses_the_context() {
+        let mut shared = Shared::<MockClipboardContext>::new();
+        let get = |context: &mut MockClipboardContext| context.get_contents();
+
+        shared
+            .with_context(|context| context.set_contents("kept".to_owned()))
+            .unwrap();
+        assert_eq!(shared.with_context(get).unwrap(), "kept");
+
+        shared.reset();
+        assert_eq!(shared.with_context(get).unwrap(), "");
+
+        shared.shutdown();
+        shared
+            .with_context(|context| context.set_contents("dropped".to_owned()))
+            .unwrap();
+        assert_eq!(shared.with_context(get).unwrap(), "");
+        assert!(shared.context.is_none());
+    }
+
+    #[test]
+    fn drops_disconnected_contexts() {
+        let mut shared = Shared::<MockClipboardContext>::new();
+        shared
+            .with_context(|context| context.set_contents("kept".to_owned()))
+            .unwrap();
+
+        let timeout = shared.with_context(|context| {
+            context.fail_next(Error::Timeout);
+            context.get_contents()
+        });
+        assert!(matches!(timeout, Err(Error::Timeout)));
+        assert!(shared.context.is_some());
+
+        let gone = shared.with_context(|context| {
+            context.fail_next(Error::NoDisplay("display server restarted".into()));
+            context.get_contents()
+        });
+        assert!(matches!(gone, Err(Error::NoDisplay(_))));
+        assert!(shared.context.is_none());
+    }
+
+    #[test]
+    fn drops_the_context_on_panic() {
+        let mut shared = Shared::<MockClipboardContext>::new();
+        shared
+            .with_context(|context| context.set_contents("kept".to_owned()))
+            .unwrap();
+
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+            shared.with_context(|_| -> Result<()> { panic!("mid-operation") })
+        }));
+        assert!(panicked.is_err());
+        assert!(shared.context.is_none());
+
+        let get = |context: &mut MockClipboardContext| context.get_contents();
+        assert_eq!(shared.with_context(get).unwrap(), "");
+    }
+}
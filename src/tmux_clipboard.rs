@@ -0,0 +1,216 @@
+//! Paste buffers of tmux.
+
+use crate::common::*;
+use crate::deadline::TIMEOUT;
+use crate::{process, Error, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// Clipboard backed by the paste buffers of a tmux server.
+///
+/// This is the natural clipboard inside tmux on a machine without a
+/// display server: copied contents can be pasted with tmux's
+/// `paste-buffer`, and text copied in copy mode can be read back.
+/// Contents are moved with `tmux load-buffer` and `tmux save-buffer`.
+///
+/// By default the most recent buffer is read, and copying creates a new
+/// automatically named buffer. [`with_buffer`](Self::with_buffer) uses
+/// a named buffer instead.
+///
+/// # Example
+///
+/// ```no_run
+/// use cli_clipboard::tmux_clipboard::TmuxClipboardContext;
+/// use cli_clipboard::{ClipboardOps, ClipboardProvider};
+///
+/// let mut ctx = TmuxClipboardContext::with_buffer("notes").unwrap();
+/// ctx.set_contents("pasted with C-b ] -b notes".to_owned()).unwrap();
+/// ```
+pub struct TmuxClipboardContext {
+    buffer: Option<String>,
+    socket: Option<PathBuf>,
+    timeout: Duration,
+}
+
+impl ClipboardProvider for TmuxClipboardContext {
+    /// Fails with `Error::NoDisplay` if tmux is not installed or its
+    /// server is not running.
+    fn new() -> Result<TmuxClipboardContext> {
+        TmuxClipboardContext::with_socket(None)
+    }
+}
+
+impl ClipboardOps for TmuxClipboardContext {
+    fn get_contents(&mut self) -> Result<String> {
+        self.get_contents_with_deadline(self.deadline())
+    }
+
+    fn set_contents(&mut self, data: String) -> Result<()> {
+        self.set_contents_with_deadline(data, self.deadline())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.clear_with_deadline(self.deadline())
+    }
+
+    /// Returns the contents of the buffer, or an empty string if there
+    /// is none.
+    fn get_contents_with_deadline(&mut self, deadline: Instant) -> Result<String> {
+        if !self.buffer_exists(deadline)? {
+            return Ok("".to_string());
+        }
+
+        let mut command = self.command();
+        command.arg("save-buffer").args(self.buffer_args()).arg("-");
+        let contents = process::run(&mut command, None, true, deadline)?;
+        Ok(String::from_utf8(contents)?)
+    }
+
+    fn set_contents_with_deadline(&mut self, data: String, deadline: Instant) -> Result<()> {
+        let mut command = self.command();
+        command.arg("load-buffer").args(self.buffer_args()).arg("-");
+        process::run(&mut command, Some(data.into_bytes()), true, deadline)?;
+        Ok(())
+    }
+
+    /// Deletes the buffer. Without a named buffer only the most recent
+    /// one is deleted, so older entries of tmux's paste history are kept
+    /// and the next most recent one is pasted from then on.
+    fn clear_with_deadline(&mut self, deadline: Instant) -> Result<()> {
+        if !self.buffer_exists(deadline)? {
+            return Ok(());
+        }
+
+        let mut command = self.command();
+        command.arg("delete-buffer").args(self.buffer_args());
+        process::run(&mut command, None, true, deadline)?;
+        Ok(())
+    }
+}
+
+impl TmuxClipboardContext {
+    /// Creates a context that reads and writes the named buffer.
+    pub fn with_buffer(buffer: impl Into<String>) -> Result<TmuxClipboardContext> {
+        let mut context = TmuxClipboardContext::new()?;
+        context.set_buffer(Some(buffer.into()));
+        Ok(context)
+    }
+
+    /// Creates a context for the tmux server listening on `socket`
+    /// instead of the default server, like `tmux -S`.
+    pub fn with_socket(socket: Option<&Path>) -> Result<TmuxClipboardContext> {
+        let context = TmuxClipboardContext {
+            buffer: None,
+            socket: socket.map(Path::to_owned),
+            timeout: TIMEOUT,
+        };
+
+        let mut command = context.command();
+        command.arg("list-sessions");
+        match process::run(&mut command, None, true, context.deadline()) {
+            Ok(_) => Ok(context),
+            Err(Error::Backend(e)) => Err(Error::NoDisplay(e)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Sets the buffer to use, or `None` for the most recent buffer.
+    pub fn set_buffer(&mut self, buffer: Option<String>) {
+        self.buffer = buffer;
+    }
+
+    /// Returns the name of the buffer in use, if any.
+    pub fn buffer(&self) -> Option<&str> {
+        self.buffer.as_deref()
+    }
+
+    /// Sets how long a tmux command may take before failing with
+    /// `Error::Timeout`. Defaults to 3 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn deadline(&self) -> Instant {
+        Instant::now() + self.timeout
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new("tmux");
+        if let Some(socket) = &self.socket {
+            command.arg("-S").arg(socket);
+        }
+        command
+    }
+
+    /// Returns the arguments selecting the buffer in use.
+    fn buffer_args(&self) -> Vec<&str> {
+        match &self.buffer {
+            Some(buffer) => vec!["-b", buffer],
+            None => Vec::new(),
+        }
+    }
+
+    fn buffer_exists(&self, deadline: Instant) -> Result<bool> {
+        let names = self.buffers(deadline)?;
+        Ok(match &self.buffer {
+            Some(buffer) => names.contains(buffer),
+            None => !names.is_empty(),
+        })
+    }
+
+    /// Lists the names of all buffers, most recent first.
+    fn buffers(&self, deadline: Instant) -> Result<Vec<String>> {
+        let mut command = self.command();
+        command.args(["list-buffers", "-F", "#{buffer_name}"]);
+
+        let names = String::from_utf8(process::run(&mut command, None, true, deadline)?)?;
+        Ok(names.lines().map(str::to_owned).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    #[ignore]
+    fn tmux_buffers() {
+        let socket = env::temp_dir().join(format!("cli-clipboard-tmux-{}", std::process::id()));
+        let status = Command::new("tmux")
+            .arg("-S")
+            .arg(&socket)
+            .args(["new-session", "-d"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let mut ctx = TmuxClipboardContext::with_socket(Some(&socket)).unwrap();
+        assert_eq!(ctx.get_contents().unwrap(), "");
+        ctx.set_contents("multi\nline".to_owned()).unwrap();
+        assert_eq!(ctx.get_contents().unwrap(), "multi\nline");
+
+        let mut named = TmuxClipboardContext::with_socket(Some(&socket)).unwrap();
+        named.set_buffer(Some("named".to_owned()));
+        assert_eq!(named.get_contents().unwrap(), "");
+        named.set_contents("named".to_owned()).unwrap();
+        assert_eq!(ctx.get_contents().unwrap(), "named");
+
+        named.clear().unwrap();
+        assert_eq!(named.get_contents().unwrap(), "");
+        assert_eq!(ctx.get_contents().unwrap(), "multi\nline");
+        ctx.set_contents("again".to_owned()).unwrap();
+        ctx.clear().unwrap();
+        assert_eq!(ctx.get_contents().unwrap(), "multi\nline");
+        ctx.clear().unwrap();
+        assert_eq!(ctx.get_contents().unwrap(), "");
+        ctx.clear().unwrap();
+
+        let _ = Command::new("tmux")
+            .arg("-S")
+            .arg(&socket)
+            .arg("kill-server")
+            .status();
+    }
+}
@@ -0,0 +1,344 @@
+//! Notifications of clipboard changes on Linux.
+//!
+//! A [`ClipboardWatcher`] reports every time the selection it watches
+//! gets new contents or is cleared, instead of having to poll
+//! `get_contents`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use cli_clipboard::watcher::ClipboardWatcher;
+//! use cli_clipboard::x11_clipboard::Clipboard;
+//!
+//! let watcher = ClipboardWatcher::<Clipboard>::new().unwrap();
+//! let stop = watcher.stop_handle();
+//! for change in watcher {
+//!     println!("{:?}", change.unwrap());
+//!     stop.stop();
+//! }
+//! ```
+
+use crate::deadline::wait_readable;
+use crate::x11_clipboard::{Clipboard, Selection};
+use crate::{Error, Result};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use wayland_client::protocol::wl_seat::WlSeat;
+use wayland_client::{global_filter, Display, EventQueue, GlobalError, GlobalManager, Main};
+use wayland_protocols::wlr::unstable::data_control::v1::client::{
+    zwlr_data_control_device_v1, zwlr_data_control_device_v1::ZwlrDataControlDeviceV1,
+    zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+};
+use x11_clipboard_crate::Context;
+use x11rb::connection::Connection;
+use x11rb::protocol::xfixes::{ConnectionExt as _, SelectionEvent, SelectionEventMask};
+use x11rb::protocol::Event;
+use x11rb::NONE;
+
+/// How often a waiting watcher checks whether it has been stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A change of the watched selection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ClipboardChange {
+    /// A client put new contents on the selection
+    Changed,
+    /// The selection was cleared, or its owner went away
+    Cleared,
+}
+
+/// Stops a [`ClipboardWatcher`], possibly from another thread
+#[derive(Clone, Debug)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    /// Ends the watcher's iteration. A watcher waiting for the next
+    /// change notices within a short polling interval.
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Iterator over the changes of a selection.
+///
+/// Like [`LinuxClipboardContext`](crate::linux_clipboard::LinuxClipboardContext),
+/// the watcher first tries to use Wayland and falls back to X11. On
+/// Wayland changes are reported by the data-control protocol, on X11 by
+/// the XFixes extension.
+///
+/// The iterator blocks until the next change and ends once
+/// [`StopHandle::stop`] is called or after returning an error.
+pub struct ClipboardWatcher<S = Clipboard>
+where
+    S: Selection,
+{
+    backend: WatcherBackend,
+    stop: Arc<AtomicBool>,
+    done: bool,
+    selection: PhantomData<S>,
+}
+
+enum WatcherBackend {
+    Wayland(WaylandWatcher),
+    X11(Box<X11Watcher>),
+}
+
+impl<S> ClipboardWatcher<S>
+where
+    S: Selection,
+{
+    /// Starts watching the selection `S`.
+    ///
+    /// Changes made before this call are not reported.
+    pub fn new() -> Result<ClipboardWatcher<S>> {
+        let backend = match WaylandWatcher::new(S::is_primary()) {
+            Ok(watcher) => WatcherBackend::Wayland(watcher),
+            Err(_) => WatcherBackend::X11(Box::new(X11Watcher::new::<S>()?)),
+        };
+
+        Ok(ClipboardWatcher {
+            backend,
+            stop: Arc::new(AtomicBool::new(false)),
+            done: false,
+            selection: PhantomData,
+        })
+    }
+
+    /// Returns a handle that ends the iteration when stopped.
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle(Arc::clone(&self.stop))
+    }
+}
+
+impl<S> Iterator for ClipboardWatcher<S>
+where
+    S: Selection,
+{
+    type Item = Result<ClipboardChange>;
+
+    fn next(&mut self) -> Option<Result<ClipboardChange>> {
+        if self.done {
+            return None;
+        }
+
+        let change = match &mut self.backend {
+            WatcherBackend::Wayland(watcher) => watcher.next_change(&self.stop),
+            WatcherBackend::X11(watcher) => watcher.next_change(&self.stop),
+        };
+
+        match change {
+            Ok(Some(change)) => Some(Ok(change)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+struct X11Watcher {
+    context: Context,
+}
+
+impl X11Watcher {
+    fn new<S: Selection>() -> Result<X11Watcher> {
+        let context = Context::new(None)?;
+        let connection = &context.connection;
+
+        connection.xfixes_query_version(5, 0)?.reply()?;
+
+        let root = connection.setup().roots[context.screen].root;
+        connection
+            .xfixes_select_selection_input(
+                root,
+                S::atom(&context.atoms),
+                SelectionEventMask::SET_SELECTION_OWNER
+                    | SelectionEventMask::SELECTION_WINDOW_DESTROY
+                    | SelectionEventMask::SELECTION_CLIENT_CLOSE,
+            )?
+            .check()?;
+
+        Ok(X11Watcher { context })
+    }
+
+    fn next_change(&mut self, stop: &AtomicBool) -> Result<Option<ClipboardChange>> {
+        loop {
+            if stop.load(Ordering::SeqCst) {
+                return Ok(None);
+            }
+
+            match self.context.connection.poll_for_event()? {
+                Some(Event::XfixesSelectionNotify(event)) => {
+                    let change = if event.subtype == SelectionEvent::SET_SELECTION_OWNER
+                        && event.owner != NONE
+                    {
+                        ClipboardChange::Changed
+                    } else {
+                        ClipboardChange::Cleared
+                    };
+                    return Ok(Some(change));
+                }
+                Some(_) => (),
+                None => thread::park_timeout(POLL_INTERVAL),
+            }
+        }
+    }
+}
+
+struct WaylandWatcher {
+    queue: EventQueue,
+    changes: Rc<RefCell<VecDeque<ClipboardChange>>>,
+    _devices: Vec<Main<ZwlrDataControlDeviceV1>>,
+}
+
+impl WaylandWatcher {
+    fn new(primary: bool) -> Result<WaylandWatcher> {
+        let display = Display::connect_to_env().map_err(|e| Error::NoDisplay(Box::new(e)))?;
+        let mut queue = display.create_event_queue();
+        let display = display.attach(queue.token());
+
+        let seats = Rc::new(RefCell::new(Vec::<Main<WlSeat>>::new()));
+        let globals = {
+            let seats = Rc::clone(&seats);
+            GlobalManager::new_with_cb(
+                &display,
+                global_filter!([WlSeat, 2, move |seat: Main<WlSeat>, _: DispatchData| {
+                    seats.borrow_mut().push(seat);
+                }]),
+            )
+        };
+        queue.sync_roundtrip(&mut (), |_, _, _| {})?;
+
+        // The primary selection needs version 2 of the protocol
+        let manager = globals
+            .instantiate_exact::<ZwlrDataControlManagerV1>(if primary { 2 } else { 1 })
+            .map_err(|e| match e {
+                GlobalError::Missing => Error::DataControlUnsupported,
+                GlobalError::VersionTooLow(_) => Error::Unsupported,
+            })?;
+
+        let changes = Rc::new(RefCell::new(VecDeque::new()));
+        let devices = seats
+            .borrow()
+            .iter()
+            .map(|seat| {
+                let device = manager.get_data_device(seat);
+                let changes = Rc::clone(&changes);
+                device.quick_assign(move |_, event, _| {
+                    use zwlr_data_control_device_v1::Event;
+                    let (offer, watched) = match event {
+                        Event::Selection { id } => (id, !primary),
+                        Event::PrimarySelection { id } => (id, primary),
+                        _ => return,
+                    };
+
+                    if watched {
+                        changes.borrow_mut().push_back(match offer {
+                            Some(_) => ClipboardChange::Changed,
+                            None => ClipboardChange::Cleared,
+                        });
+                    }
+                    // The contents are not read, so the offer is not needed
+                    if let Some(offer) = offer {
+                        offer.destroy();
+                    }
+                });
+                device
+            })
+            .collect();
+
+        // Devices announce the current selection when created, which is
+        // not a change.
+        queue.sync_roundtrip(&mut (), |_, _, _| {})?;
+        changes.borrow_mut().clear();
+
+        Ok(WaylandWatcher {
+            queue,
+            changes,
+            _devices: devices,
+        })
+    }
+
+    fn next_change(&mut self, stop: &AtomicBool) -> Result<Option<ClipboardChange>> {
+        loop {
+            if let Some(change) = self.changes.borrow_mut().pop_front() {
+                return Ok(Some(change));
+            }
+            if stop.load(Ordering::SeqCst) {
+                return Ok(None);
+            }
+
+            let display = self.queue.display();
+            match display.flush() {
+                Err(e) if e.kind() != io::ErrorKind::WouldBlock => return Err(e.into()),
+                _ => (),
+            }
+
+            // Events may already be queued, in which case there is
+            // nothing to read before dispatching them.
+            if let Some(guard) = self.queue.prepare_read() {
+                if wait_readable(display.get_connection_fd(), POLL_INTERVAL)? {
+                    match guard.read_events() {
+                        Err(e) if e.kind() != io::ErrorKind::WouldBlock => return Err(e.into()),
+                        _ => (),
+                    }
+                }
+            }
+
+            self.queue.dispatch_pending(&mut (), |_, _, _| {})?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{ClipboardOps as _, ClipboardProvider};
+    use crate::ClipboardContext;
+    use std::time::Instant;
+
+    #[test]
+    #[ignore]
+    fn stop_ends_iteration() {
+        let mut watcher =
+            ClipboardWatcher::<Clipboard>::new().expect("couldn't watch the clipboard");
+        let stop = watcher.stop_handle();
+        let started = Instant::now();
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            stop.stop();
+        });
+
+        // Other clients may change the clipboard meanwhile
+        for change in &mut watcher {
+            change.unwrap();
+        }
+        stopper.join().unwrap();
+
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert!(watcher.next().is_none());
+    }
+
+    #[test]
+    #[ignore]
+    fn reports_changes() {
+        let mut watcher =
+            ClipboardWatcher::<Clipboard>::new().expect("couldn't watch the clipboard");
+        let mut ctx = ClipboardContext::new().unwrap();
+
+        ctx.set_contents("watched".to_owned()).unwrap();
+        assert_eq!(watcher.next().unwrap().unwrap(), ClipboardChange::Changed);
+    }
+}
@@ -15,8 +15,16 @@ limitations under the License.
 */
 
 use crate::common::*;
-use crate::Result;
-use std::io::{self, Read};
+use crate::deadline::{self, Worker, TIMEOUT};
+use crate::x11_clipboard::{Clipboard, Selection};
+use crate::{mime, Error, Result};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use wayland_client::protocol::wl_seat::{self, WlSeat};
+use wayland_client::{global_filter, Display, GlobalManager, Main};
 use wl_clipboard_rs::{
     copy::{self, clear, Options, ServeRequests},
     paste, utils,
@@ -30,136 +38,322 @@ use wl_clipboard_rs::{
 /// remain after your application shuts down, consider daemonizing the
 /// clipboard components of your application.
 ///
-/// `WaylandClipboardContext` automatically detects support for and
-/// uses the primary selection protocol.
+/// Like `X11ClipboardContext`, the context accesses the selection
+/// named by its type parameter: the regular clipboard by default, or
+/// the primary selection with
+/// [`Primary`](crate::x11_clipboard::Primary).
 ///
 /// # Example
 ///
 /// ```noop
-/// use cli_clipboard::ClipboardProvider;
+/// use cli_clipboard::{ClipboardOps, ClipboardProvider};
 /// let mut clipboard = cli_clipboard::wayland_clipboard::WaylandClipboardContext::new().unwrap();
 /// clipboard.set_contents("foo bar baz".to_string()).unwrap();
 /// let contents = clipboard.get_contents().unwrap();
 ///
 /// assert_eq!(contents, "foo bar baz");
 /// ```
-pub struct WaylandClipboardContext {
-    supports_primary_selection: bool,
+pub struct WaylandClipboardContext<S = Clipboard>
+where
+    S: Selection,
+{
+    seat: Option<String>,
+    serve_limit: Option<usize>,
+    timeout: Duration,
+    worker: Worker,
+    selection: PhantomData<S>,
 }
 
-impl ClipboardProvider for WaylandClipboardContext {
+impl<S> ClipboardProvider for WaylandClipboardContext<S>
+where
+    S: Selection,
+{
     /// Constructs a new `WaylandClipboardContext` that operates on all
     /// seats using the data-control clipboard protocol.  This is
     /// intended for CLI applications that do not create Wayland
     /// windows.
     ///
-    /// Attempts to detect whether the primary selection is supported.
-    /// Assumes no primary selection support if no seats are available.
     /// In addition to returning Err on communication errors (such as
     /// when operating in an X11 environment), will also return Err if
-    /// the compositor does not support the data-control protocol.
-    fn new() -> Result<WaylandClipboardContext> {
-        let supports_primary_selection = match utils::is_primary_selection_supported() {
-            Ok(v) => v,
-            Err(utils::PrimarySelectionCheckError::NoSeats) => false,
+    /// the compositor does not support the data-control protocol, and
+    /// `Error::Unsupported` for the primary selection if the compositor
+    /// does not support it. Primary selection support cannot be
+    /// checked while no seats are available.
+    fn new() -> Result<WaylandClipboardContext<S>> {
+        match utils::is_primary_selection_supported() {
+            Ok(false) if S::is_primary() => return Err(Error::Unsupported),
+            Ok(_) | Err(utils::PrimarySelectionCheckError::NoSeats) => (),
             Err(e) => return Err(e.into()),
-        };
+        }
 
         Ok(WaylandClipboardContext {
-            supports_primary_selection,
+            seat: None,
+            serve_limit: None,
+            timeout: TIMEOUT,
+            worker: Worker::new(),
+            selection: PhantomData,
         })
     }
+}
 
-    /// Pastes from the Wayland clipboard.
-    ///
-    /// If the Wayland environment supported the primary selection when
-    /// this context was constructed, first checks the primary
-    /// selection. If pasting from the primary selection raises an
-    /// error or the primary selection is unsupported, falls back to
-    /// the regular clipboard.
+impl<S> ClipboardOps for WaylandClipboardContext<S>
+where
+    S: Selection,
+{
+    /// Pastes from the Wayland selection.
     ///
     /// An empty clipboard is not considered an error, but the
     /// clipboard must indicate a text MIME type and the contained text
     /// must be valid UTF-8.
     fn get_contents(&mut self) -> Result<String> {
-        if self.supports_primary_selection {
-            match paste::get_contents(
-                paste::ClipboardType::Primary,
-                paste::Seat::Unspecified,
-                paste::MimeType::Text,
-            ) {
-                Ok((mut reader, _)) => {
-                    // this looks weird, but rustc won't let me do it
-                    // the natural way
-                    return Ok(read_into_string(&mut reader).map_err(Box::new)?);
-                }
-                Err(e) => match e {
-                    paste::Error::NoSeats
-                    | paste::Error::ClipboardEmpty
-                    | paste::Error::NoMimeType => return Ok("".to_string()),
-                    _ => (),
-                },
-            }
+        self.get_contents_with_deadline(self.deadline())
+    }
+
+    /// Copies to the Wayland selection.
+    fn set_contents(&mut self, data: String) -> Result<()> {
+        self.set_contents_with_deadline(data, self.deadline())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.clear_with_deadline(self.deadline())
+    }
+
+    /// Pastes from the Wayland selection, failing with `Error::Timeout`
+    /// if the source client has not sent all of its data by `deadline`.
+    fn get_contents_with_deadline(&mut self, deadline: Instant) -> Result<String> {
+        match self.paste(paste::MimeType::Text, deadline) {
+            Ok(contents) => Ok(String::from_utf8(contents)?),
+            Err(Error::ClipboardEmpty | Error::FormatUnavailable) => Ok("".to_string()),
+            Err(e) => Err(e),
         }
+    }
+
+    fn set_contents_with_deadline(&mut self, data: String, deadline: Instant) -> Result<()> {
+        self.copy(data.into_bytes(), copy::MimeType::Text, deadline)
+    }
+
+    fn clear_with_deadline(&mut self, deadline: Instant) -> Result<()> {
+        let (clipboard, seat) = (Self::copy_type(), self.copy_seat());
+        self.worker.run_until(
+            deadline,
+            move || clear(clipboard, seat).map_err(Into::into),
+            |_| (),
+        )
+    }
 
-        let mut reader = match paste::get_contents(
-            paste::ClipboardType::Regular,
-            paste::Seat::Unspecified,
-            paste::MimeType::Text,
-        ) {
-            Ok((reader, _)) => reader,
-            Err(
-                paste::Error::NoSeats | paste::Error::ClipboardEmpty | paste::Error::NoMimeType,
-            ) => return Ok("".to_string()),
+    /// Pastes the given MIME type from the Wayland selection.
+    ///
+    /// Plain text MIME types accept any text format offered by the
+    /// clipboard.
+    fn get_bytes(&mut self, mime: &str) -> Result<Vec<u8>> {
+        if mime::is_text(mime) {
+            self.paste(paste::MimeType::Text, self.deadline())
+        } else {
+            self.paste(paste::MimeType::Specific(mime), self.deadline())
+        }
+    }
+
+    /// Copies bytes of the given MIME type to the Wayland selection.
+    fn set_bytes(&mut self, mime: &str, data: Vec<u8>) -> Result<()> {
+        if mime::is_text(mime) {
+            self.copy(data, copy::MimeType::Text, self.deadline())
+        } else {
+            self.copy(
+                data,
+                copy::MimeType::Specific(mime.to_owned()),
+                self.deadline(),
+            )
+        }
+    }
+
+    /// Lists the MIME types offered by the selection. An empty
+    /// selection offers none.
+    fn available_formats(&mut self) -> Result<Vec<String>> {
+        let mut formats: Vec<String> = match self.mime_types() {
+            Ok(mime_types) => mime_types.into_iter().collect(),
+            Err(paste::Error::NoSeats | paste::Error::ClipboardEmpty) => return Ok(Vec::new()),
             Err(e) => return Err(e.into()),
         };
 
-        Ok(read_into_string(&mut reader).map_err(Box::new)?)
+        formats.sort();
+        Ok(formats)
     }
 
-    /// Copies to the Wayland clipboard.
+    /// Copies several formats at once.
     ///
-    /// If the Wayland environment supported the primary selection when
-    /// this context was constructed, this will copy to both the
-    /// primary selection and the regular clipboard. Otherwise, only
-    /// the regular clipboard will be pasted to.
-    fn set_contents(&mut self, data: String) -> Result<()> {
-        let mut options = Options::new();
+    /// wl-clipboard-rs also offers the first text format under the
+    /// common plain text MIME types, so plain text formats are put
+    /// first to keep e.g. `text/html` from being offered as plain text.
+    fn set_formats(&mut self, mut formats: Vec<(String, Vec<u8>)>) -> Result<()> {
+        formats.sort_by_key(|(mime, _)| !mime::is_text(mime));
 
-        options
-            .seat(copy::Seat::All)
-            .trim_newline(false)
-            .foreground(false)
-            .serve_requests(ServeRequests::Unlimited);
+        let sources = formats
+            .into_iter()
+            .map(|(mime, data)| copy::MimeSource {
+                source: copy::Source::Bytes(data.into()),
+                mime_type: copy::MimeType::Specific(mime),
+            })
+            .collect();
 
-        if self.supports_primary_selection {
-            options.clipboard(copy::ClipboardType::Both);
-        } else {
-            options.clipboard(copy::ClipboardType::Regular);
-        }
+        let options = self.options();
+        self.worker.run_until(
+            self.deadline(),
+            move || options.copy_multi(sources).map_err(Into::into),
+            |_| (),
+        )
+    }
+}
 
-        options
-            .copy(
-                copy::Source::Bytes(data.into_bytes().into()),
-                copy::MimeType::Text,
+impl<S> WaylandClipboardContext<S>
+where
+    S: Selection,
+{
+    /// Constructs a context restricted to the named seat, see
+    /// [`seats`](Self::seats).
+    pub fn with_seat(seat: impl Into<String>) -> Result<WaylandClipboardContext<S>> {
+        let mut context = WaylandClipboardContext::new()?;
+        context.set_seat(Some(seat.into()));
+        Ok(context)
+    }
+
+    /// Restricts the context to the named seat. By default contents are
+    /// read from the compositor's default seat and copied to all seats.
+    pub fn set_seat(&mut self, seat: Option<String>) {
+        self.seat = seat;
+    }
+
+    /// Returns the seat the context is restricted to, if any.
+    pub fn seat(&self) -> Option<&str> {
+        self.seat.as_deref()
+    }
+
+    /// Lists the names of the compositor's seats, sorted.
+    ///
+    /// Seats that do not announce a name (before version 2 of the seat
+    /// protocol) are left out, since they cannot be selected.
+    pub fn seats(&self) -> Result<Vec<String>> {
+        let display = Display::connect_to_env().map_err(|e| Error::NoDisplay(Box::new(e)))?;
+        let mut queue = display.create_event_queue();
+        let display = display.attach(queue.token());
+
+        let names = Rc::new(RefCell::new(Vec::new()));
+        let _globals = {
+            let names = Rc::clone(&names);
+            GlobalManager::new_with_cb(
+                &display,
+                global_filter!([WlSeat, 2, move |seat: Main<WlSeat>, _: DispatchData| {
+                    let names = Rc::clone(&names);
+                    seat.quick_assign(move |_, event, _| {
+                        if let wl_seat::Event::Name { name } = event {
+                            names.borrow_mut().push(name);
+                        }
+                    });
+                }]),
             )
-            .map_err(Into::into)
+        };
+
+        // The first roundtrip binds the seats, the second receives
+        // their names.
+        queue.sync_roundtrip(&mut (), |_, _, _| {})?;
+        queue.sync_roundtrip(&mut (), |_, _, _| {})?;
+
+        let mut names = names.take();
+        names.sort();
+        Ok(names)
     }
 
-    fn clear(&mut self) -> Result<()> {
-        if self.supports_primary_selection {
-            clear(copy::ClipboardType::Both, copy::Seat::All).map_err(Into::into)
+    /// Stops serving copied contents after the given number of paste
+    /// requests. By default they are served until another client
+    /// copies.
+    pub fn set_serve_limit(&mut self, requests: Option<usize>) {
+        self.serve_limit = requests;
+    }
+
+    fn paste_seat(&self) -> paste::Seat<'_> {
+        match &self.seat {
+            Some(seat) => paste::Seat::Specific(seat),
+            None => paste::Seat::Unspecified,
+        }
+    }
+
+    /// Sets how long reading, setting and clearing the selection may
+    /// take before failing with `Error::Timeout`. Reads wait for the
+    /// source client to send its data. Defaults to 3 seconds.
+    ///
+    /// Sets and clears run one at a time on a thread of the context.
+    /// Those still waiting for an earlier one when they time out are
+    /// dropped, but one already talking to the compositor may complete
+    /// after its timeout.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn deadline(&self) -> Instant {
+        Instant::now() + self.timeout
+    }
+
+    fn copy_seat(&self) -> copy::Seat {
+        match &self.seat {
+            Some(seat) => copy::Seat::Specific(seat.clone()),
+            None => copy::Seat::All,
+        }
+    }
+
+    fn paste_type() -> paste::ClipboardType {
+        if S::is_primary() {
+            paste::ClipboardType::Primary
         } else {
-            clear(copy::ClipboardType::Regular, copy::Seat::All).map_err(Into::into)
+            paste::ClipboardType::Regular
         }
     }
-}
 
-fn read_into_string<R: Read>(reader: &mut R) -> io::Result<String> {
-    let mut contents = String::new();
-    reader.read_to_string(&mut contents)?;
+    fn copy_type() -> copy::ClipboardType {
+        if S::is_primary() {
+            copy::ClipboardType::Primary
+        } else {
+            copy::ClipboardType::Regular
+        }
+    }
+
+    fn paste(&self, mime_type: paste::MimeType, deadline: Instant) -> Result<Vec<u8>> {
+        let (mut reader, _) =
+            paste::get_contents(Self::paste_type(), self.paste_seat(), mime_type)?;
+
+        deadline::read_to_end(&mut reader, deadline)
+    }
+
+    fn mime_types(&self) -> std::result::Result<HashSet<String>, paste::Error> {
+        paste::get_mime_types(Self::paste_type(), self.paste_seat())
+    }
+
+    fn copy(&self, data: Vec<u8>, mime_type: copy::MimeType, deadline: Instant) -> Result<()> {
+        let options = self.options();
+        self.worker.run_until(
+            deadline,
+            move || {
+                options
+                    .copy(copy::Source::Bytes(data.into()), mime_type)
+                    .map_err(Into::into)
+            },
+            |_| (),
+        )
+    }
+
+    fn options(&self) -> Options {
+        let mut options = Options::new();
+
+        options
+            .clipboard(Self::copy_type())
+            .seat(self.copy_seat())
+            .trim_newline(false)
+            .foreground(false)
+            .serve_requests(match self.serve_limit {
+                Some(requests) => ServeRequests::Only(requests),
+                None => ServeRequests::Unlimited,
+            });
 
-    Ok(contents)
+        options
+    }
 }
 
 #[cfg(test)]
@@ -169,7 +363,7 @@ mod tests {
     #[test]
     #[ignore]
     fn wayland_test() {
-        let mut clipboard =
+        let mut clipboard: WaylandClipboardContext =
             WaylandClipboardContext::new().expect("couldn't create a Wayland clipboard");
 
         clipboard
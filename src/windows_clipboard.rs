@@ -14,9 +14,9 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use clipboard_win::{empty, get_clipboard_string, set_clipboard_string, Clipboard};
+use clipboard_win::{empty, get_clipboard_string, set_clipboard_string};
 
-use crate::common::ClipboardProvider;
+use crate::common::{ClipboardOps, ClipboardProvider};
 use crate::Result;
 
 pub struct WindowsClipboardContext;
@@ -25,7 +25,9 @@ impl ClipboardProvider for WindowsClipboardContext {
     fn new() -> Result<Self> {
         Ok(WindowsClipboardContext)
     }
+}
 
+impl ClipboardOps for WindowsClipboardContext {
     fn get_contents(&mut self) -> Result<String> {
         Ok(get_clipboard_string()?)
     }
@@ -35,7 +37,7 @@ impl ClipboardProvider for WindowsClipboardContext {
     }
 
     fn clear(&mut self) -> Result<()> {
-        let _clip = Clipboard::new_attempts(10)?;
+        let _clip = clipboard_win::Clipboard::new_attempts(10)?;
         Ok(empty()?)
     }
 }
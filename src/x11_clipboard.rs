@@ -15,14 +15,42 @@ limitations under the License.
 */
 
 use crate::common::*;
-use crate::Result;
+use crate::deadline::TIMEOUT;
+use crate::x11_server::{self, Server, Targets};
+use crate::{mime, Error, Result};
 use std::marker::PhantomData;
-use std::time::Duration;
-use x11_clipboard_crate::Clipboard as X11Clipboard;
-use x11_clipboard_crate::{Atom, Atoms};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use x11_clipboard_crate::{Atom, Atoms, Context};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{AtomEnum, ConnectionExt, PropMode, Property};
+use x11rb::protocol::Event;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::{CURRENT_TIME, NONE};
 
-pub trait Selection {
+/// How long to wait for the clipboard manager to copy our targets
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(5);
+/// Interval between checks for the selection owner's answer
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Targets plain text is offered under, matching wl-clipboard-rs
+const TEXT_TARGETS: [&str; 5] = [
+    "UTF8_STRING",
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "STRING",
+    "TEXT",
+];
+
+/// Marker type naming a selection, `Clipboard` or `Primary`
+pub trait Selection: Send + Sync + 'static {
     fn atom(atoms: &Atoms) -> Atom;
+
+    /// Whether this is the primary selection, for backends that do not
+    /// name selections by atom
+    fn is_primary() -> bool {
+        false
+    }
 }
 
 pub struct Primary;
@@ -31,6 +59,10 @@ impl Selection for Primary {
     fn atom(atoms: &Atoms) -> Atom {
         atoms.primary
     }
+
+    fn is_primary() -> bool {
+        true
+    }
 }
 
 pub struct Clipboard;
@@ -41,40 +73,370 @@ impl Selection for Clipboard {
     }
 }
 
-pub struct X11ClipboardContext<S = Clipboard>(X11Clipboard, PhantomData<S>)
+/// Lets the program serve persistent selections, see
+/// [`X11ClipboardContext::set_persistent`].
+///
+/// Call it first thing in `main`, before the program starts any threads.
+/// It returns at once, unless the process was started to serve a
+/// selection: then it serves it and exits without returning. See
+/// `examples/persistent_clipboard.rs`.
+pub fn serve_if_requested() {
+    x11_server::serve_if_requested();
+}
+
+pub struct X11ClipboardContext<S = Clipboard>
 where
-    S: Selection;
+    S: Selection,
+{
+    getter: Context,
+    server: Server,
+    display: Option<String>,
+    timeout: Duration,
+    persistent: bool,
+    persist_on_exit: bool,
+    selection: PhantomData<S>,
+}
 
 impl<S> ClipboardProvider for X11ClipboardContext<S>
 where
     S: Selection,
 {
     fn new() -> Result<X11ClipboardContext<S>> {
-        Ok(X11ClipboardContext(X11Clipboard::new()?, PhantomData))
+        X11ClipboardContext::with_display(None)
     }
+}
 
+impl<S> ClipboardOps for X11ClipboardContext<S>
+where
+    S: Selection,
+{
     fn get_contents(&mut self) -> Result<String> {
-        Ok(String::from_utf8(self.0.load(
-            S::atom(&self.0.getter.atoms),
-            self.0.getter.atoms.utf8_string,
-            self.0.getter.atoms.property,
-            Duration::from_secs(3),
-        )?)?)
+        self.get_contents_with_deadline(self.deadline())
     }
 
     fn set_contents(&mut self, data: String) -> Result<()> {
-        Ok(self.0.store(
-            S::atom(&self.0.setter.atoms),
-            self.0.setter.atoms.utf8_string,
-            data,
-        )?)
+        self.set_contents_with_deadline(data, self.deadline())
     }
 
     fn clear(&mut self) -> Result<()> {
-        Ok(self.0.store(
-            S::atom(&self.0.setter.atoms),
-            self.0.setter.atoms.utf8_string,
-            "".to_string(),
-        )?)
+        self.clear_with_deadline(self.deadline())
+    }
+
+    fn get_contents_with_deadline(&mut self, deadline: Instant) -> Result<String> {
+        match self.load(self.getter.atoms.utf8_string, deadline)? {
+            Some(contents) => Ok(String::from_utf8(contents)?),
+            None => Ok("".to_string()),
+        }
+    }
+
+    fn set_contents_with_deadline(&mut self, data: String, deadline: Instant) -> Result<()> {
+        let formats = vec![(mime::TEXT.to_string(), data.into_bytes())];
+        self.store(formats, deadline)
+    }
+
+    fn clear_with_deadline(&mut self, deadline: Instant) -> Result<()> {
+        self.server.clear(S::atom(&self.getter.atoms), deadline)
+    }
+
+    /// Loads the selection converted to the target atom named after
+    /// `mime`. Plain text MIME types are requested as `UTF8_STRING`.
+    fn get_bytes(&mut self, mime: &str) -> Result<Vec<u8>> {
+        let selection = S::atom(&self.getter.atoms);
+        let owner = self
+            .getter
+            .connection
+            .get_selection_owner(selection)?
+            .reply()?
+            .owner;
+        if owner == NONE {
+            return Err(Error::ClipboardEmpty);
+        }
+
+        let target = if mime::is_text(mime) {
+            self.getter.atoms.utf8_string
+        } else {
+            self.getter.get_atom(mime)?
+        };
+
+        self.load(target, self.deadline())?
+            .ok_or(Error::FormatUnavailable)
+    }
+
+    /// Stores the data under the target atom named after `mime`.
+    fn set_bytes(&mut self, mime: &str, data: Vec<u8>) -> Result<()> {
+        self.set_formats(vec![(mime.to_owned(), data)])
+    }
+
+    /// Asks the selection owner for its `TARGETS` and returns the ones
+    /// that name MIME types. The X11 text targets are reported as
+    /// `text/plain`.
+    fn available_formats(&mut self) -> Result<Vec<String>> {
+        let targets = match self.load(self.getter.atoms.targets, self.deadline())? {
+            Some(targets) => targets,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut formats = Vec::new();
+        for target in targets.chunks_exact(4) {
+            let target = Atom::from_ne_bytes([target[0], target[1], target[2], target[3]]);
+            let name = self.getter.connection.get_atom_name(target)?.reply()?.name;
+            let format = match &name[..] {
+                b"UTF8_STRING" => mime::TEXT.to_string(),
+                b"STRING" | b"TEXT" => "text/plain".to_string(),
+                name if name.contains(&b'/') => String::from_utf8_lossy(name).into_owned(),
+                _ => continue,
+            };
+            if !formats.contains(&format) {
+                formats.push(format);
+            }
+        }
+
+        formats.sort();
+        Ok(formats)
+    }
+
+    /// Takes ownership of the selection and offers every format as the
+    /// target atom named after its MIME type. The first plain text
+    /// format is also offered under the usual X11 text targets.
+    fn set_formats(&mut self, formats: Vec<(String, Vec<u8>)>) -> Result<()> {
+        self.store(formats, self.deadline())
+    }
+}
+
+impl X11ClipboardContext<Clipboard> {
+    /// Hands the clipboard over to the clipboard manager when this
+    /// context is dropped, see [`handoff`](Self::handoff). Errors during
+    /// the handoff are ignored. Off by default.
+    pub fn persist_on_exit(&mut self, persist: bool) {
+        self.set_persist_on_exit(persist);
+    }
+
+    /// Asks the desktop's clipboard manager to save the contents we set
+    /// on the clipboard, so that they survive our exit without
+    /// [`set_persistent`](Self::set_persistent)'s background process.
+    ///
+    /// Blocks until the manager has copied every target. Does nothing if
+    /// we do not own the clipboard, and returns `Error::Unsupported` if
+    /// no clipboard manager is running.
+    pub fn handoff(&self) -> Result<()> {
+        self.save_targets()
+    }
+}
+
+impl<S> Drop for X11ClipboardContext<S>
+where
+    S: Selection,
+{
+    fn drop(&mut self) {
+        if self.persist_on_exit {
+            let _ = self.save_targets();
+        }
+    }
+}
+
+impl<S> X11ClipboardContext<S>
+where
+    S: Selection,
+{
+    /// Connects to the named X display, or the one in `$DISPLAY` if
+    /// `None`.
+    pub fn with_display(display: Option<&str>) -> Result<X11ClipboardContext<S>> {
+        Ok(X11ClipboardContext {
+            getter: Context::new(display)?,
+            server: Server::new(display)?,
+            display: display.map(str::to_owned),
+            timeout: TIMEOUT,
+            persistent: false,
+            persist_on_exit: false,
+            selection: PhantomData,
+        })
+    }
+
+    /// Sets how long reading, setting and clearing the selection may
+    /// take before failing with `Error::Timeout`. Reads wait for the
+    /// selection owner to answer. Defaults to 3 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Keeps contents set through this context on the selection after
+    /// the process exits.
+    ///
+    /// X11 has no clipboard storage: the selection is served by the
+    /// process that set it. When persistent, every set hands the
+    /// contents to a detached background process, which serves them
+    /// until another client takes the selection and then exits. Off by
+    /// default.
+    ///
+    /// The background process runs the program's executable again, which
+    /// has to call [`serve_if_requested`] at the top of `main` to serve
+    /// the contents. Setting contents fails with `Error::Unsupported` if
+    /// the program hasn't called it.
+    pub fn set_persistent(&mut self, persistent: bool) {
+        self.persistent = persistent;
+    }
+
+    fn deadline(&self) -> Instant {
+        Instant::now() + self.timeout
+    }
+
+    /// Takes ownership of the selection, offering the formats as
+    /// described for `set_formats`.
+    fn store(&self, formats: Vec<(String, Vec<u8>)>, deadline: Instant) -> Result<()> {
+        let mut targets = Targets::new();
+        let mut text = None;
+
+        for (mime, data) in formats {
+            let data: Arc<[u8]> = data.into();
+            if text.is_none() && mime::is_text(&mime) {
+                text = Some(Arc::clone(&data));
+            }
+            targets.push((self.getter.get_atom(&mime)?, data));
+        }
+
+        if let Some(text) = text {
+            for name in TEXT_TARGETS {
+                let target = self.getter.get_atom(name)?;
+                if !targets.iter().any(|(existing, _)| *existing == target) {
+                    targets.push((target, Arc::clone(&text)));
+                }
+            }
+        }
+
+        let selection = S::atom(&self.getter.atoms);
+        if self.persistent {
+            x11_server::serve_detached(self.display.as_deref(), selection, targets, deadline)
+        } else {
+            self.server.store(selection, targets, deadline)
+        }
+    }
+
+    /// Asks the clipboard manager to copy the targets we offer for the
+    /// selection, following the freedesktop clipboard manager protocol.
+    /// Does nothing if we do not own the selection.
+    fn save_targets(&self) -> Result<()> {
+        let getter = &self.getter;
+        let selection = S::atom(&getter.atoms);
+        let targets = match self.server.targets(selection) {
+            Some(targets) => targets,
+            None => return Ok(()),
+        };
+
+        let manager = getter.get_atom("CLIPBOARD_MANAGER")?;
+        if getter
+            .connection
+            .get_selection_owner(manager)?
+            .reply()?
+            .owner
+            == NONE
+        {
+            return Err(Error::Unsupported);
+        }
+
+        // The property lists the targets the manager should save
+        let save_targets = getter.get_atom("SAVE_TARGETS")?;
+        let property = getter.atoms.property;
+        getter.connection.change_property32(
+            PropMode::REPLACE,
+            getter.window,
+            property,
+            AtomEnum::ATOM,
+            &targets,
+        )?;
+        getter
+            .connection
+            .convert_selection(getter.window, manager, save_targets, property, CURRENT_TIME)?
+            .check()?;
+
+        // Meanwhile our server answers the manager's requests for the
+        // targets.
+        let deadline = Instant::now() + HANDOFF_TIMEOUT;
+        let event = self.wait_for(deadline, |event| match event {
+            Event::SelectionNotify(event) if event.selection == manager => Some(event),
+            _ => None,
+        })?;
+
+        if event.property == NONE {
+            Err(Error::Backend(
+                "the clipboard manager refused to save the clipboard".into(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn set_persist_on_exit(&mut self, persist: bool) {
+        self.persist_on_exit = persist;
+    }
+
+    /// Converts the selection to `target`, following INCR transfers.
+    /// Returns `None` if there is no owner or the owner refused the
+    /// conversion.
+    fn load(&self, target: Atom, deadline: Instant) -> Result<Option<Vec<u8>>> {
+        let getter = &self.getter;
+        let selection = S::atom(&getter.atoms);
+        let property = getter.atoms.property;
+
+        getter
+            .connection
+            .convert_selection(getter.window, selection, target, property, CURRENT_TIME)?
+            .check()?;
+
+        let event = self.wait_for(deadline, |event| match event {
+            Event::SelectionNotify(event) if event.selection == selection => Some(event),
+            _ => None,
+        })?;
+        if event.property == NONE {
+            return Ok(None);
+        }
+
+        let reply = getter
+            .connection
+            .get_property(true, getter.window, property, AtomEnum::ANY, 0, u32::MAX)?
+            .reply()?;
+        if reply.type_ != getter.atoms.incr {
+            return Ok(Some(reply.value));
+        }
+
+        // Deleting the INCR property above asked the owner for the
+        // first chunk; an empty chunk ends the transfer.
+        let mut contents = Vec::new();
+        loop {
+            self.wait_for(deadline, |event| match event {
+                Event::PropertyNotify(event)
+                    if event.atom == property && event.state == Property::NEW_VALUE =>
+                {
+                    Some(())
+                }
+                _ => None,
+            })?;
+
+            let reply = getter
+                .connection
+                .get_property(true, getter.window, property, AtomEnum::ANY, 0, u32::MAX)?
+                .reply()?;
+            if reply.value.is_empty() {
+                return Ok(Some(contents));
+            }
+            contents.extend_from_slice(&reply.value);
+        }
+    }
+
+    /// Polls the getter connection until `matches` accepts an event.
+    fn wait_for<T, F>(&self, deadline: Instant, mut matches: F) -> Result<T>
+    where
+        F: FnMut(Event) -> Option<T>,
+    {
+        loop {
+            match self.getter.connection.poll_for_event()? {
+                Some(event) => {
+                    if let Some(value) = matches(event) {
+                        return Ok(value);
+                    }
+                }
+                None if Instant::now() >= deadline => return Err(Error::Timeout),
+                None => thread::park_timeout(POLL_INTERVAL),
+            }
+        }
     }
 }
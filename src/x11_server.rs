@@ -0,0 +1,584 @@
+use crate::deadline::Worker;
+use crate::{process, Error, Result};
+use std::cmp;
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Instant;
+use x11_clipboard_crate::error::Error as X11Error;
+use x11_clipboard_crate::{Atom, Context, Window};
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::xproto::{
+    AtomEnum, ChangeWindowAttributesAux, ClientMessageEvent, ConnectionExt, EventMask, PropMode,
+    Property, SelectionNotifyEvent, SelectionRequestEvent, SELECTION_NOTIFY_EVENT,
+};
+use x11rb::protocol::Event;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::{CURRENT_TIME, NONE};
+
+/// Size of the chunks sent to requestors during INCR transfers
+const INCR_CHUNK_SIZE: usize = 4000;
+
+/// Data offered for a selection, keyed by target atom
+pub(crate) type Targets = Vec<(Atom, Arc<[u8]>)>;
+
+type Owned = Arc<RwLock<HashMap<Atom, Targets>>>;
+
+/// Serves the selections owned by this process from a background thread.
+///
+/// Unlike the server in x11-clipboard, which offers exactly one target
+/// per selection, every selection may be offered in any number of
+/// targets.
+pub(crate) struct Server {
+    context: Arc<Context>,
+    owned: Owned,
+    stop: Arc<AtomicBool>,
+    worker: Worker,
+}
+
+/// An INCR transfer in progress, keyed by requestor window and property
+struct Transfer {
+    target: Atom,
+    data: Arc<[u8]>,
+    pos: usize,
+}
+
+impl Server {
+    pub fn new(display: Option<&str>) -> Result<Server> {
+        let context = Arc::new(Context::new(display)?);
+        let owned = Owned::default();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        {
+            let context = Arc::clone(&context);
+            let owned = Arc::clone(&owned);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || run(&context, &owned, &stop, false));
+        }
+
+        Ok(Server {
+            context,
+            owned,
+            stop,
+            worker: Worker::new(),
+        })
+    }
+
+    /// Takes ownership of `selection`, offering it in the given targets.
+    ///
+    /// If that fails, or only succeeds after the deadline, the targets
+    /// offered before are restored, and a selection that wasn't owned
+    /// before is given up again.
+    pub fn store(&self, selection: Atom, targets: Targets, deadline: Instant) -> Result<()> {
+        let (context, owned) = (Arc::clone(&self.context), Arc::clone(&self.owned));
+        let (late_context, late_owned) = (Arc::clone(&self.context), Arc::clone(&self.owned));
+
+        self.worker.run_until(
+            deadline,
+            move || {
+                let previous = offer(&owned, selection, Some(targets))?;
+                match take_ownership(&context, selection) {
+                    Ok(()) => Ok(previous),
+                    Err(e) => {
+                        offer(&owned, selection, previous)?;
+                        Err(e)
+                    }
+                }
+            },
+            move |late| {
+                if let Ok(previous) = late {
+                    let _ = restore(&late_context, &late_owned, selection, previous);
+                }
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Returns the targets we offer for `selection`, or `None` if we do
+    /// not own it.
+    pub fn targets(&self, selection: Atom) -> Option<Vec<Atom>> {
+        let owned = self.owned.read().ok()?;
+        let targets = owned.get(&selection)?;
+        Some(targets.iter().map(|(target, _)| *target).collect())
+    }
+
+    /// Drops our data for `selection` and leaves the selection without
+    /// an owner.
+    pub fn clear(&self, selection: Atom, deadline: Instant) -> Result<()> {
+        let (context, owned) = (Arc::clone(&self.context), Arc::clone(&self.owned));
+
+        self.worker.run_until(
+            deadline,
+            move || {
+                offer(&owned, selection, None)?;
+                context
+                    .connection
+                    .set_selection_owner(NONE, selection, CURRENT_TIME)?
+                    .check()?;
+                Ok(())
+            },
+            |_| (),
+        )
+    }
+}
+
+/// Sets the targets offered for `selection`, or stops offering it if
+/// `None`. Returns the targets offered before.
+fn offer(owned: &Owned, selection: Atom, targets: Option<Targets>) -> Result<Option<Targets>> {
+    let mut owned = owned.write().map_err(|_| X11Error::Lock)?;
+    Ok(match targets {
+        Some(targets) => owned.insert(selection, targets),
+        None => owned.remove(&selection),
+    })
+}
+
+/// Undoes a store that finished after its caller gave up, going back to
+/// offering `previous`.
+fn restore(
+    context: &Context,
+    owned: &Owned,
+    selection: Atom,
+    previous: Option<Targets>,
+) -> Result<()> {
+    let owner = previous.is_some();
+    offer(owned, selection, previous)?;
+
+    if !owner
+        && context
+            .connection
+            .get_selection_owner(selection)?
+            .reply()?
+            .owner
+            == context.window
+    {
+        context
+            .connection
+            .set_selection_owner(NONE, selection, CURRENT_TIME)?
+            .check()?;
+    }
+    Ok(())
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        // Wake up the serving thread so that it notices the stop flag.
+        // Once it returns the connection is closed, which releases our
+        // selections.
+        let window = self.context.window;
+        let event = ClientMessageEvent::new(32, window, AtomEnum::NONE, [0u32; 5]);
+        let _ = self
+            .context
+            .connection
+            .send_event(false, window, EventMask::NO_EVENT, event);
+        let _ = self.context.connection.flush();
+    }
+}
+
+/// Environment variable telling a process started by `serve_detached`
+/// to serve a selection instead of running `main`
+const SERVE_VAR: &str = "CLI_CLIPBOARD_SERVE_SELECTION";
+
+/// Set once the program called `serve_if_requested`, and so can serve
+/// selections when started by `serve_detached`
+static SERVES: AtomicBool = AtomicBool::new(false);
+
+/// Serves a selection instead of returning if the process was started
+/// by `serve_detached`, see
+/// [`x11_clipboard::serve_if_requested`](crate::x11_clipboard::serve_if_requested).
+pub(crate) fn serve_if_requested() {
+    SERVES.store(true, Ordering::SeqCst);
+    if env::var_os(SERVE_VAR).is_some() {
+        serve_spawned();
+    }
+}
+
+/// Hands `selection` over to a detached process that serves `targets`
+/// until another client takes ownership, so that the contents outlive
+/// this process.
+///
+/// The detached process runs this executable again, which hands over to
+/// `serve_if_requested` at the top of `main`. The targets are sent on
+/// its standard input. Fails with `Error::Unsupported` if the program
+/// never called `serve_if_requested`.
+///
+/// Returns once the detached process owns the selection, or with
+/// `Error::Timeout` if that takes until `deadline`.
+pub(crate) fn serve_detached(
+    display: Option<&str>,
+    selection: Atom,
+    targets: Targets,
+    deadline: Instant,
+) -> Result<()> {
+    if !SERVES.load(Ordering::SeqCst) {
+        return Err(Error::Unsupported);
+    }
+
+    let mut command = Command::new(env::current_exe()?);
+    command.env(SERVE_VAR, "1");
+    if let Some(display) = display {
+        command.env("DISPLAY", display);
+    }
+    spawn_server(&mut command, selection, &targets, deadline)
+}
+
+/// Runs `command` as the detached server and waits until it took the
+/// selection.
+fn spawn_server(
+    command: &mut Command,
+    selection: Atom,
+    targets: &Targets,
+    deadline: Instant,
+) -> Result<()> {
+    // The server reports whether it took the selection. It closes its
+    // standard output without writing if it failed to connect.
+    let request = encode_request(selection, targets);
+    match process::run(command, Some(request), true, deadline)?[..] {
+        [1] => Ok(()),
+        _ => Err(X11Error::Owner.into()),
+    }
+}
+
+/// Body of the process started by `serve_detached`. Never returns.
+fn serve_spawned() -> ! {
+    // Fork so that the server is adopted by init instead of waited for
+    // by `serve_detached`, and leave its session. `serve_if_requested`
+    // is called before the program starts any threads, so forking is
+    // safe.
+    unsafe {
+        if libc::setsid() == -1 || libc::fork() != 0 {
+            libc::_exit(0);
+        }
+    }
+    close_inherited();
+
+    let mut request = Vec::new();
+    let (selection, targets) = match io::stdin().read_to_end(&mut request) {
+        Ok(_) => decode_request(&request),
+        Err(_) => None,
+    }
+    .unwrap_or_else(|| unsafe { libc::_exit(1) });
+    let context = Context::new(None).unwrap_or_else(|_| unsafe { libc::_exit(1) });
+
+    let owned = Owned::default();
+    if let Ok(mut owned) = owned.write() {
+        owned.insert(selection, targets);
+    }
+    let owner = take_ownership(&context, selection).is_ok();
+    let mut stdout = io::stdout();
+    let _ = stdout
+        .write_all(&[owner as u8])
+        .and_then(|_| stdout.flush());
+    detach_std_streams();
+
+    if owner {
+        run(&context, &owned, &AtomicBool::new(false), true);
+    }
+    unsafe { libc::_exit(0) }
+}
+
+/// Encodes the selection and its targets for `serve_spawned`.
+fn encode_request(selection: Atom, targets: &Targets) -> Vec<u8> {
+    let mut request = selection.to_le_bytes().to_vec();
+    for (target, data) in targets {
+        request.extend_from_slice(&target.to_le_bytes());
+        request.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        request.extend_from_slice(data);
+    }
+    request
+}
+
+fn decode_request(mut request: &[u8]) -> Option<(Atom, Targets)> {
+    let selection = Atom::from_le_bytes(take(&mut request, 4)?.try_into().ok()?);
+    let mut targets = Targets::new();
+    while !request.is_empty() {
+        let target = Atom::from_le_bytes(take(&mut request, 4)?.try_into().ok()?);
+        let len = u64::from_le_bytes(take(&mut request, 8)?.try_into().ok()?);
+        let data = take(&mut request, usize::try_from(len).ok()?)?;
+        targets.push((target, data.into()));
+    }
+    Some((selection, targets))
+}
+
+/// Splits the first `len` bytes off `input`.
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if input.len() < len {
+        return None;
+    }
+    let (head, rest) = input.split_at(len);
+    *input = rest;
+    Some(head)
+}
+
+/// Closes every inherited descriptor except the standard streams.
+///
+/// Keeping the parent's X11 connections open would keep its windows
+/// and selections alive.
+fn close_inherited() {
+    let inherited: Vec<RawFd> = match fs::read_dir("/proc/self/fd") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .filter(|&fd| fd > 2)
+            .collect(),
+        Err(_) => return,
+    };
+    for fd in inherited {
+        unsafe { libc::close(fd) };
+    }
+}
+
+/// Points the standard streams at /dev/null, so that the parent stops
+/// waiting for our output.
+fn detach_std_streams() {
+    if let Ok(null) = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+    {
+        for fd in 0..3 {
+            unsafe { libc::dup2(null.as_raw_fd(), fd) };
+        }
+    }
+}
+
+/// Makes our window the owner of `selection` and checks that it is.
+fn take_ownership(context: &Context, selection: Atom) -> Result<()> {
+    let connection = &context.connection;
+    connection
+        .set_selection_owner(context.window, selection, CURRENT_TIME)?
+        .check()?;
+
+    if connection.get_selection_owner(selection)?.reply()?.owner == context.window {
+        Ok(())
+    } else {
+        Err(X11Error::Owner.into())
+    }
+}
+
+/// Serves the selections in `owned` until `stop` is set or, if
+/// `until_cleared`, until no selection is owned anymore.
+fn run(context: &Context, owned: &Owned, stop: &AtomicBool, until_cleared: bool) {
+    let max_length = context.connection.maximum_request_bytes();
+    let multiple = context.get_atom("MULTIPLE").unwrap_or(NONE);
+    let mut transfers = HashMap::<(Window, Atom), Transfer>::new();
+
+    while let Ok(event) = context.connection.wait_for_event() {
+        if stop.load(Ordering::SeqCst) {
+            return;
+        }
+
+        match event {
+            Event::SelectionRequest(event) => {
+                let property = reply(context, owned, &mut transfers, max_length, multiple, &event)
+                    .unwrap_or(NONE);
+
+                let _ = context.connection.send_event(
+                    false,
+                    event.requestor,
+                    EventMask::NO_EVENT,
+                    SelectionNotifyEvent {
+                        response_type: SELECTION_NOTIFY_EVENT,
+                        sequence: 0,
+                        time: event.time,
+                        requestor: event.requestor,
+                        selection: event.selection,
+                        target: event.target,
+                        property,
+                    },
+                );
+                let _ = context.connection.flush();
+            }
+            Event::PropertyNotify(event) if event.state == Property::DELETE => {
+                let key = (event.window, event.atom);
+                let done = match transfers.get_mut(&key) {
+                    Some(transfer) => {
+                        let len = cmp::min(INCR_CHUNK_SIZE, transfer.data.len() - transfer.pos);
+                        let _ = context.connection.change_property8(
+                            PropMode::REPLACE,
+                            event.window,
+                            event.atom,
+                            transfer.target,
+                            &transfer.data[transfer.pos..][..len],
+                        );
+                        transfer.pos += len;
+                        len == 0
+                    }
+                    None => continue,
+                };
+
+                if done {
+                    transfers.remove(&key);
+                }
+                let _ = context.connection.flush();
+            }
+            Event::SelectionClear(event) => {
+                if let Ok(mut owned) = owned.write() {
+                    owned.remove(&event.selection);
+                    if until_cleared && owned.is_empty() {
+                        return;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Answers a selection request by writing the requested target to the
+/// requestor's property. Returns the property written to, or `None` if
+/// the request is refused.
+fn reply(
+    context: &Context,
+    owned: &Owned,
+    transfers: &mut HashMap<(Window, Atom), Transfer>,
+    max_length: usize,
+    multiple: Atom,
+    event: &SelectionRequestEvent,
+) -> Option<Atom> {
+    let owned = owned.read().ok()?;
+    let targets = owned.get(&event.selection)?;
+
+    // Obsolete clients pass None and expect the target to be used
+    let property = if event.property == NONE {
+        event.target
+    } else {
+        event.property
+    };
+
+    if event.target != multiple {
+        let request = (event.requestor, event.target, property);
+        return convert(context, targets, transfers, max_length, multiple, request);
+    }
+
+    // MULTIPLE asks for several conversions at once, listed as pairs of
+    // target and property. Refused conversions get their property
+    // replaced by None.
+    let connection = &context.connection;
+    let pairs = connection
+        .get_property(false, event.requestor, property, AtomEnum::ANY, 0, u32::MAX)
+        .ok()?
+        .reply()
+        .ok()?;
+    let mut atoms: Vec<Atom> = pairs.value32()?.collect();
+    for pair in atoms.chunks_exact_mut(2) {
+        let request = (event.requestor, pair[0], pair[1]);
+        if convert(context, targets, transfers, max_length, multiple, request).is_none() {
+            pair[1] = NONE;
+        }
+    }
+    connection
+        .change_property32(
+            PropMode::REPLACE,
+            event.requestor,
+            property,
+            pairs.type_,
+            &atoms,
+        )
+        .ok()?;
+
+    Some(property)
+}
+
+/// Writes a single target of `targets` to a requestor's property, given
+/// as `(requestor, target, property)`.
+fn convert(
+    context: &Context,
+    targets: &Targets,
+    transfers: &mut HashMap<(Window, Atom), Transfer>,
+    max_length: usize,
+    multiple: Atom,
+    (requestor, target, property): (Window, Atom, Atom),
+) -> Option<Atom> {
+    let connection = &context.connection;
+
+    if target == context.atoms.targets {
+        let mut atoms = vec![context.atoms.targets, multiple];
+        atoms.extend(targets.iter().map(|(target, _)| *target));
+        connection
+            .change_property32(
+                PropMode::REPLACE,
+                requestor,
+                property,
+                AtomEnum::ATOM,
+                &atoms,
+            )
+            .ok()?;
+        return Some(property);
+    }
+
+    let (target, data) = targets.iter().find(|(offered, _)| *offered == target)?;
+
+    if data.len() < max_length - 24 {
+        connection
+            .change_property8(PropMode::REPLACE, requestor, property, *target, data)
+            .ok()?;
+    } else {
+        connection
+            .change_window_attributes(
+                requestor,
+                &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+            )
+            .ok()?;
+        connection
+            .change_property32(
+                PropMode::REPLACE,
+                requestor,
+                property,
+                context.atoms.incr,
+                &[data.len() as u32],
+            )
+            .ok()?;
+        transfers.insert(
+            (requestor, property),
+            Transfer {
+                target: *target,
+                data: Arc::clone(data),
+                pos: 0,
+            },
+        );
+    }
+
+    Some(property)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn encodes_requests() {
+        let targets: Targets = vec![(7, b"text".to_vec().into()), (9, Vec::new().into())];
+        let request = encode_request(42, &targets);
+        assert_eq!(decode_request(&request), Some((42, targets)));
+
+        assert_eq!(decode_request(&request[..request.len() - 1]), None);
+        assert_eq!(decode_request(&[]), None);
+    }
+
+    #[test]
+    fn spawns_the_server() {
+        // The test binary doesn't call `serve_if_requested`
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let targets: Targets = vec![(1, b"text".to_vec().into())];
+        let result = serve_detached(None, 1, targets.clone(), deadline);
+        assert!(matches!(result, Err(Error::Unsupported)), "{:?}", result);
+
+        let mut server = Command::new("sh");
+        server.args(["-c", "cat >/dev/null; printf '\\001'"]);
+        spawn_server(&mut server, 1, &targets, deadline).unwrap();
+
+        let mut failing = Command::new("sh");
+        failing.args(["-c", "cat >/dev/null"]);
+        let result = spawn_server(&mut failing, 1, &targets, deadline);
+        assert!(matches!(result, Err(Error::Backend(_))), "{:?}", result);
+    }
+}